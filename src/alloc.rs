@@ -1,22 +1,68 @@
+use core::alloc::AllocError;
 use core::alloc::Allocator;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::cell::UnsafeCell;
 use core::cmp::max;
+use core::cmp::min;
+use core::fmt;
 use core::marker::PhantomData;
 use core::mem::align_of;
+use core::mem::offset_of;
+use core::mem::size_of;
+use core::mem::ManuallyDrop;
+use core::ptr;
 use core::ptr::NonNull;
 
-use spin::Mutex;
 use typenum::consts::U16384;
 use typenum::consts::U4096;
 use typenum::consts::U65536;
 use typenum::PowerOfTwo;
 use typenum::Unsigned;
+use typenum::U;
 
+use super::backend::BlockAllocator;
+use super::backend::ConstDefault;
+use super::event::Event;
+use super::event::EventKind;
+#[cfg(feature = "event-log")]
+use super::event::EventLog;
+use super::fit::FirstFit;
+use super::fit::FitStrategy;
+use super::growth::GrowthPolicy;
 use super::hole::HeapBlock;
 use super::hole::Hole;
+use super::info::AllocFailure;
+use super::info::BlockId;
+use super::info::BlockInfo;
+use super::info::PointerKind;
+use super::info::Pressure;
+use super::info::ReserveError;
+use super::info::Stats;
+use super::lazy::LazyBlocks;
+use super::lock::PoisonMutex;
+use super::lock::RawMutex;
+use super::lock::SpinRawMutex;
+use super::region::NoBlocks;
+use super::registry::LargeRegistry;
+#[cfg(feature = "counters")]
+use super::stats::size_class;
+#[cfg(feature = "counters")]
+use super::stats::AtomicStats;
+#[cfg(feature = "counters")]
+use super::stats::ClassStats;
+#[cfg(feature = "counters")]
+use super::stats::Counters;
+#[cfg(feature = "counters")]
+use super::stats::QuickStats;
 use super::utils::align_up;
+use super::utils::checked_align_up;
+
+/// A function called with the base address and size of every new heapblock.
+type NewBlockHook = fn(usize, usize);
+
+/// A function called with every allocator event.
+type TraceHook = fn(Event);
 
 #[cfg(not(test))]
 /// A global allocator using a linked heap made of smaller blocks.
@@ -39,303 +85,5406 @@ use super::utils::align_up;
 /// * **`BA`** (block alignment): the alignment required for a heap block.
 /// * **`LS`** (large block size): the size above which an individual block is
 ///   allocated instead of using heap blocks. A typical value is 1/4th of the
-///   block size. *Undefined behaviour if not lower than the block size !*
+///   block size. Layouts that would not fit in a fresh heap block are always
+///   allocated individually. A size of zero (`U0`) disables individual
+///   allocations altogether: all layouts are then allocated in heapblocks,
+///   and layouts that would not fit in one cannot be allocated.
 /// * **`LA`** (large block alignment): the alignment required for a large block.
+/// * **`F`** (fit strategy): the [`FitStrategy`] choosing the hole of a
+///   heapblock an allocation is carved from, either [`FirstFit`] (the
+///   default) or [`BestFit`](crate::BestFit).
+/// * **`M`** (mutex): the [`RawMutex`] synchronising the allocator, spinning
+///   by default. A kernel mutex avoids busy-waiting on embedded targets.
+///
+/// # Large allocations
+///
+/// Every live large allocation is registered with the layout it was made
+/// with, in an entry stored in its last bytes. Large allocations are thus
+/// recognized by their address when freed, whatever the layout given, and are
+/// given back when the allocator is dropped. The entry is fitted in the
+/// padding to `LA` when there is room, and takes another `LA` bytes
+/// otherwise.
 ///
 /// [`linked-list-allocator`]: https://crates.io/crates/linked-list-allocator
-pub struct Deblockator<A, BS = U65536, BA = U4096, LS = U16384, LA = U4096>
-where
-    A: Allocator,
+pub struct Deblockator<
+    A,
+    BS = U65536,
+    BA = U4096,
+    LS = U16384,
+    LA = U4096,
+    F = FirstFit,
+    M = SpinRawMutex,
+> where
+    A: BlockAllocator,
     BS: Unsigned + 'static,
     BA: Unsigned + PowerOfTwo,
     LS: Unsigned,
     LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
 {
     __block_size: PhantomData<BS>,
     __block_padding: PhantomData<BA>,
     __large_size: PhantomData<LS>,
     __large_padding: PhantomData<LA>,
-    mutex: Mutex<()>,
+    __fit: PhantomData<F>,
+    mutex: PoisonMutex<M>,
     block_allocator: UnsafeCell<A>,
     first_block: UnsafeCell<Option<&'static mut HeapBlock>>,
+    over_allocate: bool,
+    size_granularity: usize,
+    retain_first_block: bool,
+    next_fit: bool,
+    rover: UnsafeCell<usize>,
+    compact_threshold: Option<f32>,
+    min_block_count: usize,
+    max_block_count: Option<usize>,
+    growth: GrowthPolicy,
+    next_block_size: UnsafeCell<usize>,
+    self_heal: bool,
+    pressure_thresholds: (usize, usize),
+    new_block_hook: UnsafeCell<Option<NewBlockHook>>,
+    trace_hook: UnsafeCell<Option<TraceHook>>,
+    unreported_blocks: UnsafeCell<usize>,
+    scratch: UnsafeCell<usize>,
+    frozen: UnsafeCell<bool>,
+    large_count: UnsafeCell<usize>,
+    large_registry: UnsafeCell<LargeRegistry>,
+    #[cfg(feature = "event-log")]
+    events: UnsafeCell<EventLog>,
+    #[cfg(feature = "counters")]
+    counters: UnsafeCell<Counters>,
+    #[cfg(feature = "counters")]
+    quick_stats: AtomicStats,
+    #[cfg(feature = "tags")]
+    tag_bytes: UnsafeCell<[usize; 256]>,
 }
 
 #[cfg(test)]
 /// Test definition with public variables.
-pub struct Deblockator<A, BS = U65536, BA = U4096, LS = U16384, LA = U4096>
-where
-    A: Alloc,
+pub struct Deblockator<
+    A,
+    BS = U65536,
+    BA = U4096,
+    LS = U16384,
+    LA = U4096,
+    F = FirstFit,
+    M = SpinRawMutex,
+> where
+    A: BlockAllocator,
     BS: Unsigned + 'static,
     BA: Unsigned + PowerOfTwo,
     LS: Unsigned,
     LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
 {
     __block_size: PhantomData<BS>,
     __block_padding: PhantomData<BA>,
     __large_size: PhantomData<LS>,
     __large_padding: PhantomData<LA>,
-    pub mutex: Mutex<()>,
+    __fit: PhantomData<F>,
+    pub mutex: PoisonMutex<M>,
     pub block_allocator: UnsafeCell<A>,
     pub first_block: UnsafeCell<Option<&'static mut HeapBlock>>,
+    pub over_allocate: bool,
+    pub size_granularity: usize,
+    pub retain_first_block: bool,
+    pub next_fit: bool,
+    pub rover: UnsafeCell<usize>,
+    pub compact_threshold: Option<f32>,
+    pub min_block_count: usize,
+    pub max_block_count: Option<usize>,
+    pub growth: GrowthPolicy,
+    pub next_block_size: UnsafeCell<usize>,
+    pub self_heal: bool,
+    pub pressure_thresholds: (usize, usize),
+    pub new_block_hook: UnsafeCell<Option<NewBlockHook>>,
+    pub trace_hook: UnsafeCell<Option<TraceHook>>,
+    pub unreported_blocks: UnsafeCell<usize>,
+    pub scratch: UnsafeCell<usize>,
+    pub frozen: UnsafeCell<bool>,
+    pub large_count: UnsafeCell<usize>,
+    pub large_registry: UnsafeCell<LargeRegistry>,
+    #[cfg(feature = "event-log")]
+    pub events: UnsafeCell<EventLog>,
+    #[cfg(feature = "counters")]
+    pub counters: UnsafeCell<Counters>,
+    #[cfg(feature = "counters")]
+    pub quick_stats: AtomicStats,
+    #[cfg(feature = "tags")]
+    pub tag_bytes: UnsafeCell<[usize; 256]>,
 }
 
-unsafe impl<A, BS, BA, LS, LA> Sync for Deblockator<A, BS, BA, LS, LA>
+unsafe impl<A, BS, BA, LS, LA, F, M> Sync for Deblockator<A, BS, BA, LS, LA, F, M>
 where
-    A: Allocator,
+    A: BlockAllocator,
     BS: Unsigned + 'static,
     BA: Unsigned + PowerOfTwo,
     LS: Unsigned,
     LA: Unsigned + PowerOfTwo,
-{}
+    F: FitStrategy,
+    M: RawMutex + Sync,
+{
+}
 
-unsafe impl<A, BS, BA, LS, LA> Send for Deblockator<A, BS, BA, LS, LA>
+unsafe impl<A, BS, BA, LS, LA, F, M> Send for Deblockator<A, BS, BA, LS, LA, F, M>
 where
-    A: Allocator,
+    A: BlockAllocator,
     BS: Unsigned + 'static,
     BA: Unsigned + PowerOfTwo,
     LS: Unsigned,
     LA: Unsigned + PowerOfTwo,
-{}
+    F: FitStrategy,
+    M: RawMutex + Send,
+{
+}
+
+/// A [`Deblockator`] parameterized with constants instead of [`typenum`] numerics.
+///
+/// The parameters have the same meaning as the ones of [`Deblockator`], and
+/// are converted with [`typenum::U`]: only sizes up to `1024` and powers of
+/// two are supported. Using an alignment that is not a power of two fails
+/// to compile.
+/// ```rust
+/// #![feature(allocator_api)]
+/// # use std::alloc::{GlobalAlloc, Layout};
+/// use deblockator::{ConstDeblockator, Deblockator, StaticPool};
+///
+/// #[repr(C, align(4096))]
+/// struct Buffers([[u8; 4096]; 2]);
+///
+/// let mut buffers = Buffers([[0; 4096]; 2]);
+/// let pool = StaticPool::new(&mut buffers.0);
+/// let deblockator: ConstDeblockator<_, 4096, 4096, 1024, 4096> = Deblockator::new(pool);
+/// # unsafe {
+/// #     let layout = Layout::from_size_align(16, 8).unwrap();
+/// #     let ptr = deblockator.alloc(layout);
+/// #     assert!(!ptr.is_null());
+/// #     deblockator.dealloc(ptr, layout);
+/// # }
+/// ```
+///
+/// The parameters are checked at compile time:
+/// ```rust,compile_fail
+/// #![feature(allocator_api)]
+/// use deblockator::{ConstDeblockator, Deblockator, StaticPool};
+///
+/// let mut buffers = [[0; 4096]; 2];
+/// let pool = StaticPool::new(&mut buffers);
+/// let deblockator: ConstDeblockator<_, 4096, 1000, 1024, 4096> = Deblockator::new(pool);
+/// ```
+///
+/// [`typenum`]: https://docs.rs/typenum/
+pub type ConstDeblockator<
+    A,
+    const BS: usize,
+    const BA: usize,
+    const LS: usize,
+    const LA: usize,
+    F = FirstFit,
+    M = SpinRawMutex,
+> = Deblockator<A, U<BS>, U<BA>, U<LS>, U<LA>, F, M>;
 
-impl<A, BS, BA, LS, LA> Default for Deblockator<A, BS, BA, LS, LA>
+impl<A, BS, BA, LS, LA, F, M> Default for Deblockator<A, BS, BA, LS, LA, F, M>
 where
-    A: Allocator + Default,
+    A: BlockAllocator + Default,
     BS: Unsigned + 'static,
     BA: Unsigned + PowerOfTwo,
     LS: Unsigned,
     LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
 {
     fn default() -> Self {
         Self::new(A::default())
     }
 }
 
-impl<A, BS, BA, LS, LA> Deblockator<A, BS, BA, LS, LA>
+impl<A, BS, BA, LS, LA, F, M> fmt::Debug for Deblockator<A, BS, BA, LS, LA, F, M>
+where
+    A: BlockAllocator,
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    /// Formats a summary of the heap usage, without allocating.
+    ///
+    /// The lock is only tried, so that formatting never deadlocks, e.g. in a
+    /// panic handler: a held or poisoned lock is reported instead. A mutex
+    /// keeping the default [`RawMutex::try_lock`] is always reported as held.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats = match self.mutex.try_lock() {
+            Some(Ok(lock)) => {
+                let stats = unsafe { self.stats_locked() };
+                lock.unlock();
+                stats
+            }
+            Some(Err(_)) => return f.write_str("Deblockator { <poisoned> }"),
+            None => return f.write_str("Deblockator { <locked> }"),
+        };
+        f.debug_struct("Deblockator")
+            .field("heapblock_count", &stats.heapblock_count)
+            .field("total_bytes", &stats.total_bytes)
+            .field("used_bytes", &stats.used_bytes)
+            .field("free_bytes", &stats.free_bytes)
+            .field("large_allocation_count", &stats.large_allocation_count)
+            .finish()
+    }
+}
+
+impl<A, BS, BA, LS, LA, F, M> Deblockator<A, BS, BA, LS, LA, F, M>
+where
+    A: BlockAllocator + ConstDefault,
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    /// Create a new allocator instance, wrapping the default underlying allocator.
+    ///
+    /// Unlike [`Default::default`], this can initialize a `static`, as a
+    /// `#[global_allocator]` for instance. See [`ConstDefault`].
+    pub const fn new_default() -> Self {
+        Self::new(A::DEFAULT)
+    }
+}
+
+impl<A, BS, BA, LS, LA, F, M> Deblockator<A, BS, BA, LS, LA, F, M>
 where
-    A: Allocator,
+    A: BlockAllocator,
     BS: Unsigned + 'static,
     BA: Unsigned + PowerOfTwo,
     LS: Unsigned,
     LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
 {
     /// Create a new allocator instance, wrapping the given allocator.
+    ///
+    /// The block size and alignment are checked at compile time: a heapblock
+    /// must be aligned for its header, and large enough to hold it and a
+    /// minimal allocation, with a size keeping its holes aligned.
     pub const fn new(alloc: A) -> Self {
+        const {
+            assert!(
+                BA::USIZE >= align_of::<HeapBlock>(),
+                "block alignment is too small"
+            );
+            assert!(
+                BS::USIZE >= size_of::<HeapBlock>() + 2 * size_of::<usize>()
+                    && BS::USIZE.is_multiple_of(align_of::<Hole>()),
+                "block size is too small or misaligned"
+            );
+        }
         Deblockator {
             __block_size: PhantomData,
             __block_padding: PhantomData,
             __large_size: PhantomData,
             __large_padding: PhantomData,
-            mutex: Mutex::new(()),
+            __fit: PhantomData,
+            mutex: PoisonMutex::new(),
             block_allocator: UnsafeCell::new(alloc),
             first_block: UnsafeCell::new(None),
+            over_allocate: false,
+            size_granularity: align_of::<Hole>(),
+            retain_first_block: true,
+            next_fit: false,
+            rover: UnsafeCell::new(0),
+            compact_threshold: None,
+            min_block_count: 0,
+            max_block_count: None,
+            growth: GrowthPolicy::Fixed,
+            next_block_size: UnsafeCell::new(BS::USIZE),
+            self_heal: false,
+            pressure_thresholds: (50, 80),
+            new_block_hook: UnsafeCell::new(None),
+            trace_hook: UnsafeCell::new(None),
+            unreported_blocks: UnsafeCell::new(0),
+            scratch: UnsafeCell::new(0),
+            frozen: UnsafeCell::new(false),
+            large_count: UnsafeCell::new(0),
+            large_registry: UnsafeCell::new(LargeRegistry::new()),
+            #[cfg(feature = "event-log")]
+            events: UnsafeCell::new(EventLog::new()),
+            #[cfg(feature = "counters")]
+            counters: UnsafeCell::new(Counters::new()),
+            #[cfg(feature = "counters")]
+            quick_stats: AtomicStats::new(),
+            #[cfg(feature = "tags")]
+            tag_bytes: UnsafeCell::new([0; 256]),
         }
     }
 
-    /// Create a kernel-compatible layout that can fit the requested layout
-    unsafe fn padded(&self, layout: Layout, align: usize) -> Layout {
-        let padding = layout.padding_needed_for(align);
-        Layout::from_size_align_unchecked(layout.size() + padding, align)
+    /// Round heapblock allocations up to the next power of two.
+    ///
+    /// This trades some memory for cheaper reallocations: growing an
+    /// allocation within its size class does not need to move it. The
+    /// actual size of an allocation is derived from its requested size, so
+    /// this must not be changed while allocations are live.
+    pub const fn over_allocate(mut self, enabled: bool) -> Self {
+        self.over_allocate = enabled;
+        self
     }
-}
 
-unsafe impl<A, BS, BA, LS, LA> GlobalAlloc for Deblockator<A, BS, BA, LS, LA>
-where
-    A: Allocator,
-    BS: Unsigned + 'static,
-    BA: Unsigned + PowerOfTwo,
-    LS: Unsigned,
-    LA: Unsigned + PowerOfTwo,
-{
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let lock = self.mutex.lock();
-        let allocator = &mut *self.block_allocator.get();
+    /// Round heapblock allocation sizes up to a multiple of `granularity`.
+    ///
+    /// A coarser granularity makes freed holes match later requests of a
+    /// similar size more often, at the cost of some internal fragmentation.
+    /// Granularities finer than the hole alignment have no effect. As with
+    /// [`over_allocate`](Self::over_allocate), this must not be changed
+    /// while allocations are live.
+    ///
+    /// # Panics
+    ///
+    /// If `granularity` is not a power of two.
+    pub const fn size_granularity(mut self, granularity: usize) -> Self {
+        assert!(
+            granularity.is_power_of_two(),
+            "granularity is not a power of two"
+        );
+        if granularity > self.size_granularity {
+            self.size_granularity = granularity;
+        }
+        self
+    }
 
-        // if the requested memory block is large, simply dedicate a single block
-        if layout.size() >= LS::to_usize() {
-            return match allocator.allocate(self.padded(layout, LA::to_usize())) {
-                Ok(ptr) => ptr.as_ptr() as *mut u8,
-                Err(_) => ::core::ptr::null_mut::<u8>(),
-            };
+    /// Keep the first heapblock allocated when it becomes empty.
+    ///
+    /// Other heapblocks are always given back to the underlying allocator
+    /// once empty. The first one is kept by default, so that a program
+    /// repeatedly allocating and freeing a small object does not allocate a
+    /// new heapblock every time. Disable this to reclaim all the memory
+    /// whenever the heap becomes empty.
+    pub const fn retain_first_block(mut self, retain: bool) -> Self {
+        self.retain_first_block = retain;
+        self
+    }
+
+    /// Start searching for a hole from the heapblock of the previous allocation.
+    ///
+    /// With this *next-fit* strategy, the heapblocks are searched in turn,
+    /// starting from the *rover*, and wrapping around to the first
+    /// heapblock. This spreads allocations over the heap instead of
+    /// repeatedly scanning the full heapblocks at its front.
+    pub const fn next_fit(mut self, enabled: bool) -> Self {
+        self.next_fit = enabled;
+        self
+    }
+
+    /// Merge contiguous heapblocks before growing a fragmented heap.
+    ///
+    /// When no heapblock fits an allocation and the free bytes outside of
+    /// the largest hole of the heap exceed `ratio` of all free bytes, the
+    /// heapblocks are merged as with [`merge_adjacent_blocks`] and scanned
+    /// again before calling the underlying allocator. Holes spanning the
+    /// boundary of two heapblocks can then serve the allocation.
+    ///
+    /// [`merge_adjacent_blocks`]: Self::merge_adjacent_blocks
+    pub const fn compact_threshold(mut self, ratio: f32) -> Self {
+        self.compact_threshold = Some(ratio);
+        self
+    }
+
+    /// Keep at least `count` heapblocks allocated, even when they are empty.
+    ///
+    /// This avoids calling the underlying allocator again when a program
+    /// repeatedly fills and empties the heap.
+    pub const fn min_block_count(mut self, count: usize) -> Self {
+        self.min_block_count = count;
+        self
+    }
+
+    /// Never keep more than `count` heapblocks allocated at once.
+    ///
+    /// Once `count` heapblocks are live, allocations that do not fit in them
+    /// fail instead of requesting a new heapblock, enforcing a memory quota
+    /// at the allocator boundary. Large allocations are not counted, and are
+    /// still made with the underlying allocator. The scratch heapblock given
+    /// to [`seed_scratch`](Self::seed_scratch) is not counted either, since
+    /// it does not come from the underlying allocator.
+    pub const fn max_block_count(mut self, count: usize) -> Self {
+        self.max_block_count = Some(count);
+        self
+    }
+
+    /// Check the free lists for corruption before using them to allocate.
+    ///
+    /// When an invalid hole is found, the free list of the heapblock is
+    /// truncated right before it, leaking the memory it described instead of
+    /// dereferencing garbage. This is recorded as an [`EventKind::Heal`]
+    /// event when the event log is enabled.
+    ///
+    /// This makes every allocation walk the free lists once more, and only
+    /// protects against corrupted links, not against corrupted user data.
+    pub const fn self_heal(mut self, enabled: bool) -> Self {
+        self.self_heal = enabled;
+        self
+    }
+
+    /// Choose the size of new heapblocks, `BS` bytes by default.
+    ///
+    /// With a [`GrowthPolicy::Geometric`] policy, each heapblock is larger
+    /// than the previous one, so a growing heap calls the underlying
+    /// allocator less often. The sizes never shrink back, even when
+    /// heapblocks are released. Larger heapblocks are never merged.
+    ///
+    /// # Panics
+    ///
+    /// If the growth factor is zero.
+    pub const fn growth_policy(mut self, policy: GrowthPolicy) -> Self {
+        if let GrowthPolicy::Geometric { factor, .. } = policy {
+            assert!(factor != 0, "growth factor is zero");
         }
+        self.growth = policy;
+        self
+    }
 
-        // Pad the layout to the minimum legal size
-        let block_layout = {
-            let size = max(HeapBlock::<BS>::min_size(), layout.size());
-            Layout::from_size_align_unchecked(align_up(size, align_of::<Hole>()), layout.align())
-        };
+    /// Set the utilization percentages at which the pressure becomes medium and high.
+    ///
+    /// The defaults are 50% and 80%. See [`pressure`](Self::pressure) for the
+    /// way utilization is computed.
+    pub const fn pressure_thresholds(mut self, medium: usize, high: usize) -> Self {
+        self.pressure_thresholds = (medium, high);
+        self
+    }
 
-        // traverse the heap blocks to find an allocatable block
+    /// Set a function to call whenever a new heapblock is allocated.
+    ///
+    /// The hook receives the base address and the size of the heapblock. It
+    /// is called after the allocator lock is released, and may thus allocate.
+    /// Large allocations do not trigger the hook.
+    pub fn set_new_block_hook(&self, hook: fn(base: usize, size: usize)) {
+        let lock = self.mutex.lock_unchecked();
+        unsafe { *self.new_block_hook.get() = Some(hook) };
+        lock.unlock();
+    }
+
+    /// Set a function to call with every allocator event.
+    ///
+    /// The hook receives the same [`Event`]s as the event log, for every
+    /// allocation, deallocation, new heapblock and freed heapblock, with the
+    /// address and size of the memory concerned. It is called with the
+    /// allocator lock held, right after the operation succeeded, and must
+    /// thus not allocate nor deallocate with this allocator.
+    pub fn set_trace_hook(&self, hook: fn(event: Event)) {
+        let lock = self.mutex.lock_unchecked();
+        unsafe { *self.trace_hook.get() = Some(hook) };
+        lock.unlock();
+    }
+
+    /// Forbid calling the underlying allocator until [`unfreeze`](Self::unfreeze).
+    ///
+    /// While frozen, allocations are only served from the free space of the
+    /// current heapblocks: allocations that would need a new heapblock, or
+    /// a large allocation, fail instead of growing the heap.
+    pub fn freeze(&self) {
+        let lock = self.mutex.lock_unchecked();
+        unsafe { *self.frozen.get() = true };
+        lock.unlock();
+    }
+
+    /// Allow growing the heap again after [`freeze`](Self::freeze).
+    pub fn unfreeze(&self) {
+        let lock = self.mutex.lock_unchecked();
+        unsafe { *self.frozen.get() = false };
+        lock.unlock();
+    }
+
+    /// Returns the average number of holes examined per heapblock allocation.
+    ///
+    /// A growing value hints at a fragmented heap, or at a block size too
+    /// small for the workload.
+    #[cfg(feature = "counters")]
+    pub fn avg_scan_length(&self) -> f32 {
+        let counters = self.counters();
+        match counters.allocations {
+            0 => 0.0,
+            n => counters.scanned as f32 / n as f32,
+        }
+    }
+
+    /// Returns how the heapblock allocations of each size class were served.
+    ///
+    /// See [`SIZE_CLASSES`](crate::SIZE_CLASSES) for the bounds of each class.
+    #[cfg(feature = "counters")]
+    pub fn class_stats(&self) -> [ClassStats; crate::SIZE_CLASSES] {
+        self.counters().classes
+    }
+
+    /// Returns the number of allocations served from the heapblocks.
+    #[cfg(feature = "counters")]
+    pub fn small_alloc_count(&self) -> usize {
+        self.counters().allocations
+    }
+
+    /// Returns the number of allocations made with the underlying allocator.
+    ///
+    /// A high proportion of large allocations hints at a large size `LS`
+    /// too low for the workload.
+    #[cfg(feature = "counters")]
+    pub fn large_alloc_count(&self) -> usize {
+        self.counters().large_allocations
+    }
+
+    /// Returns the number of deallocations into the heapblocks.
+    #[cfg(feature = "counters")]
+    pub fn small_dealloc_count(&self) -> usize {
+        self.counters().small_deallocations
+    }
+
+    /// Returns the number of deallocations made with the underlying allocator.
+    #[cfg(feature = "counters")]
+    pub fn large_dealloc_count(&self) -> usize {
+        self.counters().large_deallocations
+    }
+
+    /// Returns the highest number of bytes allocated at once.
+    ///
+    /// Both heapblock and large allocations are counted, with their
+    /// requested sizes. This is the peak since the allocator was created, or
+    /// since the last call to [`reset_peak`](Self::reset_peak).
+    #[cfg(feature = "counters")]
+    pub fn peak_usage(&self) -> usize {
+        self.counters().peak_used_bytes
+    }
+
+    /// Reset the peak usage to the number of bytes currently allocated.
+    #[cfg(feature = "counters")]
+    pub fn reset_peak(&self) {
+        let lock = self.mutex.lock_unchecked();
+        unsafe {
+            let counters = &mut *self.counters.get();
+            counters.peak_used_bytes = counters.used_bytes;
+        }
+        lock.unlock();
+    }
+
+    /// Returns a copy of the counters.
+    #[cfg(feature = "counters")]
+    fn counters(&self) -> Counters {
+        let lock = self.mutex.lock_unchecked();
+        let counters = unsafe { *self.counters.get() };
+        lock.unlock();
+        counters
+    }
+
+    /// Read approximate usage statistics without locking the allocator.
+    ///
+    /// The statistics are maintained atomically, so they can be polled by a
+    /// monitoring thread without stalling allocations, but may be slightly
+    /// stale while the allocator is being used.
+    #[cfg(feature = "counters")]
+    pub fn quick_stats(&self) -> QuickStats {
+        self.quick_stats.snapshot()
+    }
+
+    /// Create a new allocator instance, with enough heapblocks to allocate `bytes`.
+    ///
+    /// Like `Vec::with_capacity`, this avoids calling the underlying
+    /// allocator until the reserved memory is exhausted.
+    pub fn with_capacity(alloc: A, bytes: usize) -> Result<Self, AllocError> {
+        let deblockator = Self::new(alloc);
+        let blocks = bytes.div_ceil(HeapBlock::<BS>::capacity());
+        unsafe {
+            let mut next_block: *mut Option<&mut HeapBlock> = deblockator.first_block.get();
+            for _ in 0..blocks {
+                let block: *mut HeapBlock = deblockator.new_block()?;
+                deblockator.set_link(next_block, Some(&mut *block));
+                next_block = &mut (*block).next;
+            }
+        }
+        Ok(deblockator)
+    }
+
+    /// Allocate `n` empty heapblocks up front, linked after the current ones.
+    ///
+    /// This moves the calls to the underlying allocator out of latency
+    /// sensitive code: allocations are then served from the reserved
+    /// heapblocks until they are full. Empty heapblocks are only released
+    /// by a deallocation into them, or by [`trim`](Self::trim) and
+    /// [`reclaim`](Self::reclaim). If the underlying allocator fails, or if
+    /// the allocator is poisoned or frozen, the heapblocks created so far
+    /// are kept and their number is returned in the error.
+    pub fn reserve_blocks(&self, n: usize) -> Result<(), ReserveError> {
+        let lock = self.mutex.lock().map_err(|_| ReserveError { created: 0 })?;
+        let reserved = unsafe { self.reserve_blocks_locked(n) };
+        lock.unlock();
+        self.report_new_blocks();
+        reserved
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn reserve_blocks_locked(&self, n: usize) -> Result<(), ReserveError> {
         let mut next_block: *mut Option<&mut HeapBlock> = self.first_block.get();
         while let Some(ref mut block) = *next_block {
-            if let Ok(ptr) = block.allocate_first_fit(block_layout) {
-                return ptr.as_ptr() as *mut u8;
-            };
             next_block = &mut block.next;
         }
+        for created in 0..n {
+            let block: *mut HeapBlock = match self.new_block() {
+                Ok(block) => block,
+                Err(_) => return Err(ReserveError { created }),
+            };
+            self.set_link(next_block, Some(&mut *block));
+            next_block = &mut (*block).next;
+        }
+        Ok(())
+    }
 
-        // No block can contain the requested layout: allocate a new one !
-        let new_heap_layout = Layout::from_size_align_unchecked(BS::to_usize(), BA::to_usize());
-        let new_heap_ptr = match allocator.allocate(new_heap_layout) {
-            Ok(ptr) => NonNull::new(ptr.as_ptr() as *mut HeapBlock).unwrap(),
-            Err(_) => return ::core::ptr::null_mut::<u8>(),
-            // Err(_) => return 0xDEADBEEF as usize as *mut _,
-        };
+    /// Use `scratch` as a heapblock, placed before any other heapblock.
+    ///
+    /// Since heapblocks are searched in order, small allocations are served
+    /// from the scratch buffer until it is full, and only then from
+    /// heapblocks of the underlying allocator. The scratch heapblock is never
+    /// released, trimmed or merged. Returns `false` if a scratch buffer was
+    /// seeded already, if `scratch` is too small to hold a heapblock, or if
+    /// the allocator is poisoned.
+    pub fn seed_scratch(&self, scratch: &'static mut [u8]) -> bool {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let seeded = unsafe { self.seed_scratch_locked(scratch) };
+                lock.unlock();
+                seeded
+            }
+            Err(_) => false,
+        }
+    }
 
-        // Initialize the block and use it to allocate
-        let new_block = HeapBlock::<BS>::new(new_heap_ptr);
-        let new_block_ptr = match new_block.allocate_first_fit(block_layout) {
-            Ok(mem) => mem.as_ptr() as *mut _,
-            Err(_) => return ::core::ptr::null_mut::<u8>(),
-            // Err(_) => return 0xCAFEBABE as usize as *mut _,
-        };
-        *next_block = Some(new_block);
+    /// Must be called with the lock held.
+    unsafe fn seed_scratch_locked(&self, scratch: &'static mut [u8]) -> bool {
+        let start = scratch.as_mut_ptr() as usize;
+        let base = align_up(start, align_of::<HeapBlock>());
+        let end = (start + scratch.len()) & !(align_of::<Hole>() - 1);
+        if *self.scratch.get() != 0
+            || end < base + size_of::<HeapBlock>() + HeapBlock::<BS>::min_size()
+        {
+            return false;
+        }
 
-        drop(lock);
-        new_block_ptr
+        let block = HeapBlock::<BS>::new(NonNull::new_unchecked(base as *mut _), end - base, false);
+        block.next = (*self.first_block.get()).take();
+        block.seal();
+        *self.first_block.get() = Some(block);
+        *self.scratch.get() = base;
+        true
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let lock = self.mutex.lock();
-        if layout.size() >= LS::to_usize() {
-            let allocator = &mut *self.block_allocator.get();
-            allocator.deallocate(
-                NonNull::new(ptr).unwrap(),
-                self.padded(layout, LA::to_usize()),
-            );
-        } else {
-            let mut block: *mut Option<&mut HeapBlock> = self.first_block.get();
-            while let Some(ref mut b) = *block {
-                if b.contains(ptr as *const u8) {
-                    b.deallocate(NonNull::new_unchecked(ptr), layout);
-                    return;
-                }
-                block = &mut b.next;
+    /// Copy the most recent allocator events to `buf`, oldest first.
+    ///
+    /// Returns the number of events written, which is at most `buf.len()`
+    /// and at most [`EVENT_LOG_LEN`](crate::EVENT_LOG_LEN).
+    #[cfg(feature = "event-log")]
+    pub fn recent_events(&self, buf: &mut [Event]) -> usize {
+        // the event log stays readable for post-mortem debugging
+        let lock = self.mutex.lock_unchecked();
+        let count = unsafe { (*self.events.get()).recent(buf) };
+        lock.unlock();
+        count
+    }
+
+    /// Allocate memory as described by `layout`, reporting why it failed.
+    ///
+    /// The failure is the one met by the allocation itself, e.g. the heap
+    /// being frozen with [`freeze`](Self::freeze) or holding its
+    /// [`max_block_count`](Self::max_block_count) heapblocks.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have a non-zero size, as for [`GlobalAlloc::alloc`].
+    pub unsafe fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocFailure> {
+        self.alloc_impl(layout, 0)
+    }
+
+    /// Allocate memory as described by `layout`, accounted under `tag`.
+    ///
+    /// The memory must be deallocated with [`GlobalAlloc::dealloc`] as usual.
+    /// Memory allocated without a tag is accounted under tag `0`.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have a non-zero size, as for [`GlobalAlloc::alloc`].
+    #[cfg(feature = "tags")]
+    pub unsafe fn alloc_tagged(&self, layout: Layout, tag: u8) -> *mut u8 {
+        self.alloc_impl(layout, tag)
+            .map_or(::core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    /// Returns the number of bytes currently allocated under each tag.
+    #[cfg(feature = "tags")]
+    pub fn used_bytes_by_tag(&self) -> [usize; 256] {
+        let lock = self.mutex.lock_unchecked();
+        let used = unsafe { *self.tag_bytes.get() };
+        lock.unlock();
+        used
+    }
+
+    /// Reallocate the memory at `ptr` from `layout` to `new_layout`.
+    ///
+    /// Unlike [`GlobalAlloc::realloc`], the alignment may change: the memory
+    /// is moved if its current address does not satisfy the new alignment.
+    /// Returns a null pointer if the memory could not be reallocated, in
+    /// which case the memory at `ptr` is left untouched.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with `layout`, and
+    /// `new_layout` must have a non-zero size.
+    pub unsafe fn realloc_aligned(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> *mut u8 {
+        self.realloc_impl(NonNull::new_unchecked(ptr), layout, new_layout)
+            .map_or(::core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    /// Allocate up to `n` blocks of memory with the same `layout`.
+    ///
+    /// The allocations are made under a single lock, each of them as with
+    /// [`GlobalAlloc::alloc`], so that consecutive allocations are usually
+    /// carved next to each other. The pointers are written to `out`, and each
+    /// of them must be deallocated independently with `layout`.
+    ///
+    /// Returns the number of successful allocations, which is at most
+    /// `out.len()`.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have a non-zero size, as for [`GlobalAlloc::alloc`].
+    pub unsafe fn alloc_batch(&self, layout: Layout, n: usize, out: &mut [*mut u8]) -> usize {
+        let count = min(n, out.len());
+        let lock = match self.mutex.lock() {
+            Ok(lock) => lock,
+            Err(_) => return 0,
+        };
+
+        let mut done = 0;
+        while done < count {
+            match self.alloc_locked(layout) {
+                Ok(ptr) => out[done] = ptr.as_ptr(),
+                Err(_) => break,
             }
-            panic!("double free !")
+            done += 1;
         }
-        drop(lock);
+
+        lock.unlock();
+        self.report_new_blocks();
+        done
     }
-}
 
-#[cfg(test)]
-mod test {
+    /// Allocate memory as described by `layout`, examining at most `max_scan` holes.
+    ///
+    /// This bounds the latency of the allocation: `None` is returned if no
+    /// suitable hole was found within the budget. The underlying allocator
+    /// is never called, so large layouts and allocations that would need a
+    /// new heapblock also return `None`.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`GlobalAlloc::alloc`].
+    pub unsafe fn alloc_bounded(&self, layout: Layout, max_scan: usize) -> Option<*mut u8> {
+        let lock = self.mutex.lock().ok()?;
+        let ptr = self.alloc_bounded_locked(layout, max_scan);
+        lock.unlock();
+        ptr
+    }
 
-    use super::*;
+    /// Must be called with the lock held.
+    unsafe fn alloc_bounded_locked(&self, layout: Layout, max_scan: usize) -> Option<*mut u8> {
+        let tagged = self.tagged(layout);
+        if Self::is_large_size(tagged.size()) {
+            return None;
+        }
+        let block_layout = self.block_layout(tagged)?;
+        let mut scanned = 0;
+        let mut block: *mut Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut b) = *block {
+            if scanned >= max_scan {
+                break;
+            }
+            b.verify();
+            if let Ok(ptr) = b.allocate_with::<F>(block_layout, &mut scanned, max_scan) {
+                self.count_alloc(block_layout, scanned, false);
+                self.track(ptr.as_ptr(), layout, 0);
+                self.record(EventKind::Alloc, ptr.as_ptr() as usize, layout.size());
+                return Some(ptr.as_ptr());
+            }
+            block = &mut b.next;
+        }
+        None
+    }
 
-    use core::alloc::AllocErr;
-    use core::mem::size_of;
+    /// Returns the identifier of the heapblock containing `ptr`.
+    ///
+    /// Returns `None` if `ptr` is not within a heapblock, e.g. for large
+    /// allocations, or if the allocator is poisoned. The identifier can be
+    /// passed to [`Deblockator::dealloc_in_block`] to speed up deallocation,
+    /// or to [`Deblockator::alloc_in`] to allocate next to `ptr`.
+    pub fn block_id(&self, ptr: *const u8) -> Option<BlockId> {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let id = unsafe { self.block_id_locked(ptr as usize) };
+                lock.unlock();
+                id
+            }
+            Err(_) => None,
+        }
+    }
 
-    use typenum::consts::U2048;
+    /// Must be called with the lock held.
+    unsafe fn block_id_locked(&self, addr: usize) -> Option<BlockId> {
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            if b.contains(addr as *const u8) {
+                return Some(BlockId(*b as *const HeapBlock as usize));
+            }
+            block = &b.next;
+        }
+        None
+    }
 
-    struct MockAlloc {
-        pub allocated: [bool; 3],
-        pub blocks: [[u8; 4096]; 3],
+    /// Allocate memory as described by `layout` within the heapblock `block`.
+    ///
+    /// This allows placing related allocations close to each other: only
+    /// the heapblock identified by `block`, as returned by
+    /// [`Deblockator::block_id`] for a previous allocation, is considered.
+    /// Returns a null pointer if the layout does not fit in that heapblock,
+    /// if it would be allocated individually, or if the heapblock was
+    /// released since, instead of allocating it elsewhere. The memory must
+    /// be deallocated with [`GlobalAlloc::dealloc`] as usual.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have a non-zero size, as for [`GlobalAlloc::alloc`].
+    pub unsafe fn alloc_in(&self, block: BlockId, layout: Layout) -> *mut u8 {
+        let lock = match self.mutex.lock() {
+            Ok(lock) => lock,
+            Err(_) => return ::core::ptr::null_mut::<u8>(),
+        };
+        let ptr = self.alloc_in_locked(block, layout);
+        lock.unlock();
+        ptr
     }
 
-    impl MockAlloc {
-        pub fn new() -> Self {
-            Self {
-                allocated: [false; 3],
-                blocks: [[0; 4096], [0; 4096], [0; 4096]],
+    /// Must be called with the lock held.
+    unsafe fn alloc_in_locked(&self, id: BlockId, layout: Layout) -> *mut u8 {
+        let tagged = self.tagged(layout);
+        let block_layout = match self.block_layout(tagged) {
+            Some(block_layout) if !self.is_large(tagged) => block_layout,
+            _ => return ::core::ptr::null_mut::<u8>(),
+        };
+        let mut block: *mut Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut b) = *block {
+            if *b as *mut HeapBlock as usize == id.0 {
+                let mut scanned = 0;
+                return match self.try_block_locked(b, block_layout, &mut scanned) {
+                    Some(ptr) => {
+                        self.track(ptr, layout, 0);
+                        self.record(EventKind::Alloc, ptr as usize, layout.size());
+                        ptr
+                    }
+                    None => ::core::ptr::null_mut::<u8>(),
+                };
             }
+            block = &mut b.next;
         }
+        ::core::ptr::null_mut::<u8>()
     }
 
-    unsafe impl Alloc for MockAlloc {
-        unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
-            for i in 0..self.blocks.len() {
-                if !self.allocated[i] {
-                    self.allocated[i] = true;
-                    return NonNull::new(self.blocks[i].as_mut().as_mut_ptr()).ok_or(AllocErr);
+    /// Deallocate the memory at `ptr`, looking it up in `block_hint` first.
+    ///
+    /// This avoids traversing the heapblocks when freeing many allocations
+    /// known to belong to the same heapblock. If `ptr` is not within the
+    /// hinted heapblock, the heapblocks are traversed as with
+    /// [`GlobalAlloc::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// The same as [`GlobalAlloc::dealloc`]. Additionally, `block_hint` must
+    /// identify a heapblock that was not released since, which is the case
+    /// if it was returned by [`Deblockator::block_id`] for an allocation
+    /// still live in that heapblock.
+    pub unsafe fn dealloc_in_block(&self, ptr: *mut u8, layout: Layout, block_hint: BlockId) {
+        // the memory is leaked if the heap may be inconsistent
+        if let Ok(lock) = self.mutex.lock() {
+            self.record(EventKind::Dealloc, ptr as usize, layout.size());
+            self.untrack(ptr, layout);
+            let layout = self.tagged(layout);
+            if !self.dealloc_hinted_locked(ptr, layout, block_hint) {
+                self.dealloc_untagged_locked(ptr, layout);
+            }
+            lock.unlock();
+        }
+    }
+
+    /// Deallocate `ptr` from the hinted heapblock, if it belongs to it.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn dealloc_hinted_locked(&self, ptr: *mut u8, layout: Layout, hint: BlockId) -> bool {
+        let b = &mut *(hint.0 as *mut HeapBlock);
+        let block_layout = match self.block_layout(layout) {
+            Some(block_layout) if !Self::is_large_size(layout.size()) && b.contains(ptr) => {
+                block_layout
+            }
+            _ => return false,
+        };
+        b.verify();
+        b.deallocate(NonNull::new_unchecked(ptr), block_layout);
+        self.count_dealloc(false);
+        if b.is_empty() {
+            // only an empty heapblock requires finding the link to it
+            let mut block: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+            while let Some(ref mut next) = *block {
+                if *next as *mut HeapBlock as usize == hint.0 {
+                    self.release_if_empty(block);
+                    break;
                 }
+                block = &mut next.next;
             }
-            Err(AllocErr)
         }
+        true
+    }
 
-        unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-            for i in 0..self.blocks.len() {
-                if ptr.as_ptr() == self.blocks[i].as_mut().as_mut_ptr() {
-                    if !self.allocated[i] {
-                        panic!("double free")
-                    } else {
-                        self.allocated[i] = false;
-                        return;
-                    }
+    /// Call `f` with every word of `region` pointing into live heap memory.
+    ///
+    /// This is a conservative check meant for leak analysis: any word whose
+    /// value falls within a heapblock, outside of the free holes, is reported,
+    /// regardless of whether it is actually used as a pointer. Large
+    /// allocations do not belong to any heapblock, and are never reported.
+    ///
+    /// The lock is released before calling `f`, which may thus allocate.
+    /// Nothing is reported if the allocator is poisoned.
+    pub fn scan_for_references(&self, region: &[usize], mut f: impl FnMut(usize)) {
+        for &word in region {
+            let live = match self.mutex.lock() {
+                Ok(lock) => {
+                    let live = unsafe { self.is_live_locked(word) };
+                    lock.unlock();
+                    live
                 }
+                Err(_) => return,
+            };
+            if live {
+                f(word);
             }
-            panic!("no such block !")
         }
     }
 
-    #[test]
-    /// Test the mock allocator works as expected.
-    fn mockalloc() {
-        unsafe {
-            let mut ma = MockAlloc::new();
-            let layout = Layout::from_size_align_unchecked(4096, 4096);
+    /// Must be called with the lock held.
+    unsafe fn is_live_locked(&self, addr: usize) -> bool {
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            let start = *b as *const HeapBlock as usize + size_of::<HeapBlock>();
+            if start <= addr && addr < b.end() {
+                return !b.in_hole(addr);
+            }
+            block = &b.next;
+        }
+        false
+    }
 
-            let pt1 = ma.alloc(layout).expect("could not allocate block 1");
-            let pt2 = ma.alloc(layout).expect("could not allocate block 2");
-            let pt3 = ma.alloc(layout).expect("could not allocate block 3");
-            ma.alloc(layout).expect_err("all blocks were not allocated");
+    /// Classify `ptr` according to the memory it points to.
+    ///
+    /// This allows rejecting invalid pointers before deallocating them, e.g.
+    /// a pointer to a free hole, which denotes a double free. Other
+    /// addresses are only told apart from foreign ones if the underlying
+    /// allocator implements [`BlockAllocator::owns`].
+    pub fn verify_pointer(&self, ptr: *const u8) -> PointerKind {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let kind = unsafe { self.verify_pointer_locked(ptr as usize) };
+                lock.unlock();
+                kind
+            }
+            Err(_) => PointerKind::Unknown,
+        }
+    }
 
-            for i in 0..3 {
-                assert!(ma.allocated[i]);
+    /// Check whether `ptr` points to memory managed by this allocator.
+    ///
+    /// This is the case for any address within a heapblock or a live large
+    /// allocation, and for other blocks of the underlying allocator if it
+    /// implements [`BlockAllocator::owns`]. Returns `false` if the allocator
+    /// is poisoned.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        match self.verify_pointer(ptr) {
+            PointerKind::Header | PointerKind::Hole | PointerKind::Small | PointerKind::Large => {
+                true
             }
+            PointerKind::Foreign | PointerKind::Unknown => false,
+        }
+    }
 
-            ma.dealloc(pt1, layout);
-            assert!(!ma.allocated[0]);
+    /// Must be called with the lock held.
+    unsafe fn verify_pointer_locked(&self, addr: usize) -> PointerKind {
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            let base = *b as *const HeapBlock as usize;
+            if base <= addr && addr < b.end() {
+                return if addr < base + size_of::<HeapBlock>() {
+                    PointerKind::Header
+                } else if b.in_hole(addr) {
+                    PointerKind::Hole
+                } else {
+                    PointerKind::Small
+                };
+            }
+            block = &b.next;
+        }
 
-            ma.dealloc(pt3, layout);
-            assert!(!ma.allocated[2]);
+        let registry = &*self.large_registry.get();
+        if registry
+            .entries()
+            .any(|(base, layout)| base <= addr && addr < base + layout.size())
+        {
+            return PointerKind::Large;
+        }
 
-            let pt4 = ma.alloc(layout).expect("could not allocate block 4");
-            assert!(ma.allocated[0]);
-            assert!(!ma.allocated[2]);
-            assert_eq!(pt4.as_ptr(), pt1.as_ptr());
+        let allocator = &*self.block_allocator.get();
+        match NonNull::new(addr as *mut u8).map(|ptr| allocator.owns(ptr)) {
+            Some(Some(true)) => PointerKind::Large,
+            Some(Some(false)) | None => PointerKind::Foreign,
+            Some(None) => PointerKind::Unknown,
         }
     }
 
-    #[test]
-    /// Check the underlying blocks are allocated as expected.
-    fn deblockator_blocks() {
-        let ma = MockAlloc::new();
-        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+    /// Call `f` with every span of allocated heap memory, in address order.
+    ///
+    /// Heapblocks are visited by ascending base address, and the spans of
+    /// each heapblock from low to high addresses. Adjacent allocations are
+    /// reported as a single span, and large allocations are not reported.
+    ///
+    /// The lock is released before calling `f`, which may thus allocate.
+    /// Nothing is reported if the allocator is poisoned.
+    pub fn foreach_allocation_ordered(&self, mut f: impl FnMut(*mut u8, usize)) {
+        let mut from = 0;
+        loop {
+            let span = match self.mutex.lock() {
+                Ok(lock) => {
+                    let span = unsafe { self.next_span_locked(from) };
+                    lock.unlock();
+                    span
+                }
+                Err(_) => return,
+            };
+            match span {
+                Some((start, size)) => {
+                    f(start as *mut u8, size);
+                    from = start + 1;
+                }
+                None => return,
+            }
+        }
+    }
 
-        unsafe {
-            // quick accessor to the allocated blocks
-            let allocated = || va.block_allocator.get().read().allocated;
-            let blocks = || va.block_allocator.get().read().blocks;
+    /// Returns the lowest span of allocated heap memory starting at or after `from`.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn next_span_locked(&self, from: usize) -> Option<(usize, usize)> {
+        let mut next: Option<(usize, usize)> = None;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            if let Some(span) = b.used_span(from) {
+                if next.is_none_or(|(start, _)| span.0 < start) {
+                    next = Some(span);
+                }
+            }
+            block = &b.next;
+        }
+        next
+    }
 
-            // Allocate a single boxed u32
-            let layout = Layout::from_size_align(32, 8).expect("bad layout");
-            let ptr1 = NonNull::new(va.alloc(layout)).expect("could not allocate 1");
-            ::core::ptr::write(ptr1.as_ptr(), 255);
-            assert_eq!(allocated(), [true, false, false]);
+    /// Describe the heapblocks, in the order they are searched.
+    ///
+    /// The descriptions of the first heapblocks are written to `out`, and
+    /// the number of descriptions written is returned. The lock is only
+    /// held while taking the snapshot. Nothing is written if the allocator
+    /// is poisoned.
+    pub fn snapshot_blocks(&self, out: &mut [BlockInfo]) -> usize {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let count = unsafe { self.snapshot_blocks_locked(out) };
+                lock.unlock();
+                count
+            }
+            Err(_) => 0,
+        }
+    }
 
-            // Allocate a second boxed u32
-            let ptr2 = NonNull::new(va.alloc(layout)).expect("could not allocate 2");
-            ::core::ptr::write(ptr2.as_ptr(), 254);
-            assert_eq!(allocated(), [true, false, false]);
+    /// Must be called with the lock held.
+    unsafe fn snapshot_blocks_locked(&self, out: &mut [BlockInfo]) -> usize {
+        let mut count = 0;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            match out.get_mut(count) {
+                Some(info) => *info = b.info(),
+                None => break,
+            }
+            count += 1;
+            block = &b.next;
+        }
+        count
+    }
 
-            // Allocate a large object to the second block
-            let layout = Layout::from_size_align(3129, 4096).expect("bad layout");
-            let ptr3 = NonNull::new(va.alloc(layout)).expect("could not allocate 3");
-            assert_eq!(allocated(), [true, true, false]);
+    /// Describe the heapblocks, sorted by base address.
+    ///
+    /// Heapblocks are linked in the order they were allocated, which does not
+    /// always match their address order. The descriptions of the lowest
+    /// heapblocks are written to `buf`, and the number of descriptions
+    /// written is returned. Nothing is written if the allocator is poisoned.
+    pub fn iter_blocks_ordered(&self, buf: &mut [BlockInfo]) -> usize {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let count = unsafe { self.iter_blocks_ordered_locked(buf) };
+                lock.unlock();
+                count
+            }
+            Err(_) => 0,
+        }
+    }
 
-            // Deallocate the first u32
-            let layout = Layout::from_size_align(32, 8).expect("bad layout");
-            va.dealloc(ptr1.as_ptr(), layout);
+    /// Must be called with the lock held.
+    unsafe fn iter_blocks_ordered_locked(&self, buf: &mut [BlockInfo]) -> usize {
+        let mut count = 0;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            // insertion sort, dropping the highest block when the buffer is full
+            let info = b.info();
+            let mut i = count;
+            while i > 0 && buf[i - 1].base > info.base {
+                if i < buf.len() {
+                    buf[i] = buf[i - 1];
+                }
+                i -= 1;
+            }
+            if i < buf.len() {
+                buf[i] = info;
+                count = min(count + 1, buf.len());
+            }
+            block = &b.next;
+        }
+        count
+    }
 
-            // FIXME: Reallocate the first u32 (hopefully at the same place)
-            let ptr4 = NonNull::new(va.alloc(layout)).expect("could not allocate 4");
-            assert_eq!(ptr4.as_ptr(), ptr1.as_ptr());
+    /// Call the new block hook with every heapblock not reported yet.
+    ///
+    /// Must be called without the lock held.
+    fn report_new_blocks(&self) {
+        loop {
+            let lock = match self.mutex.lock() {
+                Ok(lock) => lock,
+                Err(_) => return,
+            };
+            let (hook, base) = unsafe {
+                let unreported = &mut *self.unreported_blocks.get();
+                match *self.new_block_hook.get() {
+                    Some(hook) if *unreported > 0 => {
+                        let base = self.nth_block_from_end(*unreported);
+                        *unreported -= 1;
+                        (hook, base)
+                    }
+                    _ => {
+                        *unreported = 0;
+                        lock.unlock();
+                        return;
+                    }
+                }
+            };
+            lock.unlock();
+            if let Some((base, size)) = base {
+                hook(base, size);
+            }
+        }
+    }
+
+    /// Returns the number of heapblocks in the heap.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn count_blocks(&self) -> usize {
+        let mut blocks = 0;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            blocks += 1;
+            block = &b.next;
+        }
+        blocks
+    }
+
+    /// Returns the number of heapblocks obtained from the underlying allocator.
+    ///
+    /// This excludes the scratch heapblock. Must be called with the lock held.
+    unsafe fn count_backend_blocks(&self) -> usize {
+        let scratch = (*self.scratch.get() != 0) as usize;
+        self.count_blocks() - scratch
+    }
+
+    /// Returns the address of the `n`-th heapblock starting from the end of the heap.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn nth_block_from_end(&self, n: usize) -> Option<(usize, usize)> {
+        let blocks = self.count_blocks();
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        for _ in 0..blocks.checked_sub(n)? {
+            block = &(*block).as_ref()?.next;
+        }
+        (*block)
+            .as_ref()
+            .map(|b| (*b as *const HeapBlock as usize, b.size))
+    }
+
+    /// Free empty heapblocks until at most `target_bytes` are used by heapblocks.
+    ///
+    /// Unlike automatic reclamation, this also frees the first heapblock if
+    /// empty, but never goes below the [`min_block_count`](Self::min_block_count).
+    /// Large allocations are not taken into account.
+    ///
+    /// Returns the number of bytes given back to the underlying allocator.
+    pub fn trim(&self, target_bytes: usize) -> usize {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let (_, released) = unsafe { self.trim_locked(target_bytes) };
+                lock.unlock();
+                released
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Free all the empty heapblocks in a single pass.
+    ///
+    /// This is the same as [`trim`](Self::trim) with a target of `0` bytes,
+    /// and allows batching reclamation at a quiescent point instead of
+    /// relying on deallocations. The first heapblock is freed as well if
+    /// empty, and the order of the remaining heapblocks is preserved.
+    ///
+    /// Returns the number of heapblocks and of bytes given back to the
+    /// underlying allocator.
+    pub fn reclaim(&self) -> (usize, usize) {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let reclaimed = unsafe { self.trim_locked(0) };
+                lock.unlock();
+                reclaimed
+            }
+            Err(_) => (0, 0),
+        }
+    }
+
+    /// Consume the allocator and return the wrapped block allocator.
+    ///
+    /// All the heapblocks are given back to the underlying allocator first.
+    /// If some memory is still allocated, either in a heapblock or as a
+    /// large allocation, or if the allocator is poisoned, the allocator is
+    /// returned unchanged so that the live allocations stay valid. Forgetting
+    /// it instead of dropping it would leak its heapblocks.
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(self) -> Result<A, Self> {
+        unsafe {
+            if self.mutex.is_poisoned() || *self.large_count.get() > 0 {
+                return Err(self);
+            }
+            let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+            while let Some(ref b) = *block {
+                if !b.is_empty() {
+                    return Err(self);
+                }
+                block = &b.next;
+            }
+
+            let mut this = ManuallyDrop::new(self);
+            this.release_blocks();
+            // the other fields hold no resources
+            ptr::drop_in_place(&mut this.mutex);
+            Ok(ptr::read(this.block_allocator.get()))
+        }
+    }
+
+    /// Give all the large allocations back to the underlying allocator.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn release_large(&self) {
+        while let Some((addr, padded)) = (*self.large_registry.get()).pop() {
+            self.free_large_locked(addr as *mut u8, padded);
+        }
+    }
+
+    /// Give all the heapblocks back to the underlying allocator.
+    ///
+    /// The scratch heapblock is only unlinked, since it was not allocated by
+    /// the underlying allocator. Must be called with the lock held.
+    unsafe fn release_blocks(&self) {
+        let head = self.first_block.get();
+        while let Some(ref mut b) = *head {
+            if self.is_scratch(b) {
+                *head = b.next.take();
+            } else {
+                self.free_block(head);
+            }
+        }
+    }
+
+    /// Returns the number of heapblocks and of bytes released.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn trim_locked(&self, target_bytes: usize) -> (usize, usize) {
+        let mut blocks = 0;
+        let mut size = 0;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            blocks += 1;
+            size += b.size;
+            block = &b.next;
+        }
+
+        let (mut count, mut released) = (0, 0);
+        let mut block: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+        while size - released > target_bytes && blocks > self.min_block_count {
+            match *block {
+                Some(ref mut b) if b.is_empty() && !self.is_scratch(b) => {
+                    released += self.free_block(block);
+                    blocks -= 1;
+                    count += 1;
+                }
+                Some(ref mut b) => block = &mut b.next,
+                None => break,
+            }
+        }
+        (count, released)
+    }
+
+    /// Returns a coarse memory pressure level.
+    ///
+    /// The level is derived from the utilization of the heapblocks: the
+    /// proportion of their capacity not available in the largest hole of
+    /// each heapblock. Free bytes outside of the largest holes are counted as
+    /// used, since a fragmented heap can rarely serve larger allocations.
+    /// The level is compared to the [`pressure_thresholds`](Self::pressure_thresholds).
+    ///
+    /// A poisoned allocator is always under high pressure.
+    pub fn pressure(&self) -> Pressure {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let pressure = unsafe { self.pressure_locked() };
+                lock.unlock();
+                pressure
+            }
+            Err(_) => Pressure::High,
+        }
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn pressure_locked(&self) -> Pressure {
+        let mut capacity = 0;
+        let mut available = 0;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            let info = b.info();
+            capacity += info.size - size_of::<HeapBlock>();
+            available += info.largest_hole;
+            block = &b.next;
+        }
+
+        let utilization = match capacity {
+            0 => 0,
+            _ => (capacity - available) * 100 / capacity,
+        };
+        let (medium, high) = self.pressure_thresholds;
+        if utilization >= high {
+            Pressure::High
+        } else if utilization >= medium {
+            Pressure::Medium
+        } else {
+            Pressure::Low
+        }
+    }
+
+    /// Returns a snapshot of the heap usage.
+    ///
+    /// The heapblocks are walked once with the lock held, and nothing is
+    /// allocated. All values are zero if the allocator is poisoned.
+    pub fn stats(&self) -> Stats {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let stats = unsafe { self.stats_locked() };
+                lock.unlock();
+                stats
+            }
+            Err(_) => Stats::default(),
+        }
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn stats_locked(&self) -> Stats {
+        let mut stats = Stats {
+            large_allocation_count: *self.large_count.get(),
+            ..Stats::default()
+        };
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            let info = b.info();
+            stats.heapblock_count += 1;
+            stats.total_bytes += info.size;
+            stats.used_bytes += info.size - info.free_bytes;
+            stats.free_bytes += info.free_bytes;
+            stats.largest_free_block = max(stats.largest_free_block, info.largest_hole);
+            block = &b.next;
+        }
+        stats
+    }
+
+    /// Returns the base address and the fragmentation of the most fragmented heapblock.
+    ///
+    /// The fragmentation of a heapblock is the proportion of its free bytes
+    /// outside of its largest hole, between `0.0` for a heapblock with a
+    /// single hole and close to `1.0` for a heapblock with many small holes.
+    /// Returns `None` if there are no heapblocks, or if the allocator is
+    /// poisoned.
+    pub fn most_fragmented_block(&self) -> Option<(usize, f32)> {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let worst = unsafe { self.most_fragmented_block_locked() };
+                lock.unlock();
+                worst
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn most_fragmented_block_locked(&self) -> Option<(usize, f32)> {
+        let mut worst: Option<(usize, f32)> = None;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            let info = b.info();
+            let fragmentation = match info.free_bytes {
+                0 => 0.0,
+                free => 1.0 - info.largest_hole as f32 / free as f32,
+            };
+            if worst.is_none_or(|(_, f)| fragmentation > f) {
+                worst = Some((info.base, fragmentation));
+            }
+            block = &b.next;
+        }
+        worst
+    }
+
+    /// Count the holes of the whole heap in `buckets`, by power of two.
+    ///
+    /// Bucket `i` counts the holes of `2^i` inclusive to `2^(i+1)` exclusive
+    /// bytes, and the last bucket all larger ones. The buckets are reset
+    /// first, and left empty if the allocator is poisoned.
+    pub fn histogram_of_holes<const N: usize>(&self, buckets: &mut [u64; N]) {
+        *buckets = [0; N];
+        if let Ok(lock) = self.mutex.lock() {
+            unsafe {
+                let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+                while let Some(ref b) = *block {
+                    b.hole_histogram(buckets);
+                    block = &b.next;
+                }
+            }
+            lock.unlock();
+        }
+    }
+
+    /// Returns the proportion of free bytes outside of the largest hole of the heap.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn fragmentation_locked(&self) -> f32 {
+        self.stats_locked().fragmentation()
+    }
+
+    /// Check that no free memory was written to since it was deallocated.
+    ///
+    /// Every hole is checked to still be filled with the poison pattern,
+    /// except for its header. Returns the address of the first hole that
+    /// was written to, which hints at a use after free, or a null pointer
+    /// if the allocator is poisoned by a panic.
+    #[cfg(feature = "poison")]
+    pub fn check_poison(&self) -> Result<(), *mut u8> {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let checked = unsafe { self.check_poison_locked() };
+                lock.unlock();
+                checked
+            }
+            Err(_) => Err(::core::ptr::null_mut()),
+        }
+    }
+
+    /// Must be called with the lock held.
+    #[cfg(feature = "poison")]
+    unsafe fn check_poison_locked(&self) -> Result<(), *mut u8> {
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            b.verify();
+            b.check_poison()?;
+            block = &b.next;
+        }
+        Ok(())
+    }
+
+    /// Check the integrity of the hole lists of all the heapblocks.
+    ///
+    /// The holes of each heapblock must be sorted by address, aligned,
+    /// large enough to hold their header, and must neither overlap nor
+    /// exceed the heapblock. Returns a description of the first violation
+    /// found, or of the poisoning of the allocator.
+    #[cfg(feature = "validate")]
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let validated = unsafe { self.validate_locked() };
+                lock.unlock();
+                validated
+            }
+            Err(_) => Err("allocator poisoned by a panic"),
+        }
+    }
+
+    /// Must be called with the lock held.
+    #[cfg(feature = "validate")]
+    unsafe fn validate_locked(&self) -> Result<(), &'static str> {
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            b.verify();
+            b.validate()?;
+            block = &b.next;
+        }
+        Ok(())
+    }
+
+    /// Returns the base address of the heapblock the next allocation is tried in first.
+    ///
+    /// Returns `None` if the search starts from the first heapblock, e.g.
+    /// when [`next_fit`](Self::next_fit) is disabled, or if the allocator
+    /// is poisoned.
+    pub fn rover_position(&self) -> Option<usize> {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let rover = unsafe { *self.rover.get() };
+                lock.unlock();
+                Some(rover).filter(|&rover| rover != 0)
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Make the next allocation start searching from the first heapblock.
+    pub fn reset_rover(&self) {
+        if let Ok(lock) = self.mutex.lock() {
+            unsafe {
+                *self.rover.get() = match *self.first_block.get() {
+                    Some(ref b) if self.next_fit => *b as *const HeapBlock as usize,
+                    _ => 0,
+                };
+            }
+            lock.unlock();
+        }
+    }
+
+    /// Merge heapblocks that are contiguous in memory.
+    ///
+    /// The underlying allocator may hand out blocks next to each other: each
+    /// such pair is merged into a single heapblock, which can then serve
+    /// allocations spanning both blocks. Returns the number of merges, or
+    /// `0` if the allocator is poisoned.
+    pub fn merge_adjacent_blocks(&self) -> usize {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let merges = unsafe { self.merge_adjacent_blocks_locked() };
+                lock.unlock();
+                merges
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn merge_adjacent_blocks_locked(&self) -> usize {
+        let mut merges = 0;
+        let mut block: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut b) = *block {
+            // look for the heapblock starting where this one ends
+            let end = b.end();
+            let mut merged = false;
+            let mut link: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+            while let Some(ref mut other) = *link {
+                if *other as *mut HeapBlock as usize != end
+                    || self.is_scratch(b)
+                    || self.is_scratch(other)
+                    || b.grown
+                    || other.grown
+                {
+                    link = &mut other.next;
+                    continue;
+                }
+                let other = (*link).take().unwrap();
+                self.set_link(link, other.next.take());
+                if *self.rover.get() == end {
+                    *self.rover.get() = 0;
+                }
+                b.absorb(other);
+                #[cfg(feature = "counters")]
+                self.quick_stats.block_free();
+                merges += 1;
+                merged = true;
+                break;
+            }
+            // try this heapblock again, as it may now be followed by another
+            if !merged {
+                block = &mut b.next;
+            }
+        }
+        merges
+    }
+
+    /// Check whether allocating `layout` would require calling the underlying allocator.
+    ///
+    /// Returns `false` if one of the existing heapblocks can fit the layout.
+    /// Large layouts are always allocated with the underlying allocator, and
+    /// a poisoned allocator cannot allocate at all: `true` is returned in
+    /// both cases. Nothing is allocated or modified.
+    pub fn would_grow(&self, layout: Layout) -> bool {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let grow = unsafe { self.would_grow_locked(layout) };
+                lock.unlock();
+                grow
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn would_grow_locked(&self, layout: Layout) -> bool {
+        let layout = self.tagged(layout);
+        if Self::is_large_size(layout.size()) {
+            return true;
+        }
+        let block_layout = match self.block_layout(layout) {
+            Some(block_layout) => block_layout,
+            None => return true,
+        };
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            if b.fits(block_layout) {
+                return false;
+            }
+            block = &b.next;
+        }
+        true
+    }
+
+    /// Check whether a panic occurred while the allocator lock was held.
+    ///
+    /// A poisoned allocator refuses to allocate, and leaks deallocated
+    /// memory, since its heap may have been left in an inconsistent state.
+    pub fn is_poisoned(&self) -> bool {
+        self.mutex.is_poisoned()
+    }
+
+    /// Clear the poisoned state of the allocator.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the panic that poisoned the allocator did not
+    /// leave the heap in an inconsistent state.
+    pub unsafe fn clear_poison(&self) {
+        self.mutex.clear_poison();
+    }
+
+    /// Release the allocator lock held by a context that cannot release it.
+    ///
+    /// This is a recovery hook for abort handlers, when a thread died while
+    /// holding the lock, which would otherwise deadlock every later call.
+    /// The allocator is poisoned, since the heap may be inconsistent.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held, and its holder must never resume: this can
+    /// release a lock held by another thread.
+    pub unsafe fn force_unlock(&self) {
+        self.mutex.force_unlock();
+    }
+
+    /// Record an event in the event log, if enabled, and pass it to the trace hook.
+    ///
+    /// Must be called with the lock held.
+    #[inline]
+    unsafe fn record(&self, kind: EventKind, addr: usize, size: usize) {
+        #[cfg(feature = "event-log")]
+        (*self.events.get()).push(kind, addr, size);
+        if let Some(hook) = *self.trace_hook.get() {
+            hook(Event { kind, addr, size });
+        }
+    }
+
+    /// Account for an allocation served from the heapblocks, if enabled.
+    ///
+    /// `grown` tells whether a new heapblock was required. Must be called
+    /// with the lock held.
+    #[inline]
+    #[cfg_attr(not(feature = "counters"), allow(unused_variables))]
+    unsafe fn count_alloc(&self, layout: Layout, scanned: usize, grown: bool) {
+        #[cfg(feature = "counters")]
+        {
+            let counters = &mut *self.counters.get();
+            counters.allocations += 1;
+            counters.scanned += scanned;
+            let class = &mut counters.classes[size_class(layout.size())];
+            if grown {
+                class.grown += 1;
+            } else {
+                class.reused += 1;
+            }
+        }
+    }
+
+    /// Account for a deallocation, into a heapblock or a `large` one, if enabled.
+    ///
+    /// Must be called with the lock held.
+    #[inline]
+    #[cfg_attr(not(feature = "counters"), allow(unused_variables))]
+    unsafe fn count_dealloc(&self, large: bool) {
+        #[cfg(feature = "counters")]
+        {
+            let counters = &mut *self.counters.get();
+            if large {
+                counters.large_deallocations += 1;
+            } else {
+                counters.small_deallocations += 1;
+            }
+        }
+    }
+
+    /// Extend the layout with room for the allocation tag, if enabled.
+    #[inline]
+    unsafe fn tagged(&self, layout: Layout) -> Layout {
+        #[cfg(feature = "tags")]
+        return Layout::from_size_align_unchecked(layout.size() + 1, layout.align());
+        #[cfg(not(feature = "tags"))]
+        layout
+    }
+
+    /// Account for a new allocation at `ptr`, and store its tag if enabled.
+    ///
+    /// Must be called with the lock held.
+    #[inline]
+    #[cfg_attr(not(feature = "tags"), allow(unused_variables))]
+    unsafe fn track(&self, ptr: *mut u8, layout: Layout, tag: u8) {
+        #[cfg(feature = "counters")]
+        {
+            let counters = &mut *self.counters.get();
+            counters.used_bytes += layout.size();
+            counters.peak_used_bytes = max(counters.peak_used_bytes, counters.used_bytes);
+            self.quick_stats.alloc(layout.size());
+        }
+        #[cfg(feature = "tags")]
+        {
+            ptr.add(layout.size()).write(tag);
+            (*self.tag_bytes.get())[tag as usize] += layout.size();
+        }
+    }
+
+    /// Account for the allocation at `ptr` going away, and retrieve its tag.
+    ///
+    /// Must be called with the lock held.
+    #[inline]
+    #[cfg_attr(not(feature = "tags"), allow(unused_variables))]
+    unsafe fn untrack(&self, ptr: *mut u8, layout: Layout) -> u8 {
+        #[cfg(feature = "counters")]
+        {
+            (*self.counters.get()).used_bytes -= layout.size();
+            self.quick_stats.dealloc(layout.size());
+        }
+        #[cfg(feature = "tags")]
+        {
+            let tag = ptr.add(layout.size()).read();
+            (*self.tag_bytes.get())[tag as usize] -= layout.size();
+            tag
+        }
+        #[cfg(not(feature = "tags"))]
+        0
+    }
+
+    /// Allocate and initialize a new heapblock, without linking it to the heap.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn new_block(&self) -> Result<&'static mut HeapBlock, AllocFailure> {
+        if *self.frozen.get() {
+            return Err(AllocFailure::NoFit);
+        }
+        if let Some(max) = self.max_block_count {
+            if self.count_backend_blocks() >= max {
+                return Err(AllocFailure::NoFit);
+            }
+        }
+        let allocator = &*self.block_allocator.get();
+        let size = *self.next_block_size.get();
+        let layout = Layout::from_size_align_unchecked(size, BA::to_usize());
+        let ptr = allocator
+            .allocate(layout)
+            .map_err(|_| AllocFailure::Exhausted)?
+            .cast::<HeapBlock>();
+        // a faulty allocator could return a block wrapping around the address
+        // space, or overlapping one of the current heapblocks
+        let start = ptr.as_ptr() as usize;
+        let valid = match start.checked_add(size) {
+            Some(end) => !self.overlaps_heap(start, end),
+            None => false,
+        };
+        if !valid {
+            allocator.deallocate(ptr.cast(), layout);
+            return Err(AllocFailure::Exhausted);
+        }
+        *self.unreported_blocks.get() += 1;
+        #[cfg(feature = "counters")]
+        self.quick_stats.block_create();
+        self.record(EventKind::BlockCreate, ptr.as_ptr() as usize, size);
+        *self.next_block_size.get() = self.growth.next_size(size, BS::to_usize());
+        let block = HeapBlock::<BS>::new(ptr, size, allocator.zeroes_blocks());
+        block.grown = size != BS::to_usize();
+        block.seal();
+        Ok(block)
+    }
+
+    /// Check whether the range from `start` to `end` overlaps one of the heapblocks.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn overlaps_heap(&self, start: usize, end: usize) -> bool {
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            if start < b.end() && (*b as *const HeapBlock as usize) < end {
+                return true;
+            }
+            block = &b.next;
+        }
+        false
+    }
+
+    /// Check whether `block` is the scratch heapblock, which must never be released.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn is_scratch(&self, block: &HeapBlock) -> bool {
+        block as *const HeapBlock as usize == *self.scratch.get()
+    }
+
+    /// Store `block` in `link`, either the head of the heap or the `next` field of a heapblock.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn set_link(
+        &self,
+        link: *mut Option<&'static mut HeapBlock>,
+        block: Option<&'static mut HeapBlock>,
+    ) {
+        *link = block;
+        if link != self.first_block.get() {
+            let owner = (link as *mut u8).sub(offset_of!(HeapBlock, next)) as *mut HeapBlock;
+            (*owner).seal();
+        }
+    }
+
+    /// Unlink the heapblock at `link` and give it back to the underlying allocator.
+    ///
+    /// Returns the size of the heapblock. Must be called with the lock held.
+    unsafe fn free_block(&self, link: *mut Option<&'static mut HeapBlock>) -> usize {
+        let block = match (*link).take() {
+            Some(block) => block,
+            None => return 0,
+        };
+        self.set_link(link, block.next.take());
+        #[cfg(feature = "counters")]
+        self.quick_stats.block_free();
+
+        // merged heapblocks are made of several blocks of the underlying
+        // allocator, while grown heapblocks are a single larger one
+        let (base, size) = (block as *mut HeapBlock as *mut u8, block.size);
+        let chunk = if block.grown { size } else { BS::to_usize() };
+        if *self.rover.get() == base as usize {
+            *self.rover.get() = 0;
+        }
+        self.record(EventKind::BlockFree, base as usize, size);
+        let allocator = &*self.block_allocator.get();
+        let layout = Layout::from_size_align_unchecked(chunk, BA::to_usize());
+        for offset in (0..size).step_by(chunk) {
+            allocator.deallocate(NonNull::new_unchecked(base.add(offset)), layout);
+        }
+        size
+    }
+
+    /// Pad the layout to the minimum legal size of a heapblock allocation.
+    ///
+    /// Returns `None` if the padded size overflows.
+    fn block_layout(&self, layout: Layout) -> Option<Layout> {
+        let mut size = max(HeapBlock::<BS>::min_size(), layout.size());
+        if self.over_allocate {
+            size = size.checked_next_power_of_two()?;
+        }
+        let size = checked_align_up(size, self.size_granularity)?;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
+
+    /// Check whether a layout must be allocated with the underlying allocator.
+    ///
+    /// This is the case for large layouts, but also for smaller layouts whose
+    /// alignment would not let them fit even in a brand new heapblock. The
+    /// latter may still fit in a merged heapblock, which is checked first.
+    unsafe fn is_large(&self, layout: Layout) -> bool {
+        Self::is_large_size(layout.size())
+            || self.block_layout(layout).is_none_or(|block_layout| {
+                !HeapBlock::<BS>::fits_fresh(block_layout, BS::to_usize(), BA::to_usize())
+            })
+    }
+
+    /// Check whether layouts of `size` bytes are always allocated with the underlying allocator.
+    ///
+    /// This is never the case with a large size `LS` of zero.
+    fn is_large_size(size: usize) -> bool {
+        LS::to_usize() != 0 && size >= LS::to_usize()
+    }
+
+    /// Check whether `bytes` could be allocated without running out of memory.
+    ///
+    /// This counts the free space left in the current heapblocks, and the
+    /// number of bytes the underlying allocator reports as remaining through
+    /// [`BlockAllocator::remaining`]. The missing heapblocks are sized after
+    /// the growth policy, and must be allowed by the heapblock count cap and
+    /// not be prevented by [`freeze`](Self::freeze). Nothing is allocated:
+    /// `false` is only returned when the request definitely cannot be
+    /// satisfied.
+    pub fn try_reserve(&self, bytes: usize) -> bool {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let reservable = unsafe { self.try_reserve_locked(bytes) };
+                lock.unlock();
+                reservable
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Must be called with the lock held.
+    unsafe fn try_reserve_locked(&self, bytes: usize) -> bool {
+        // count the free space in the existing heapblocks
+        let mut free = 0;
+        let mut block: *const Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref b) = *block {
+            free += b.free_bytes();
+            block = &b.next;
+        }
+
+        let mut missing = bytes.saturating_sub(free);
+        if missing == 0 {
+            return true;
+        } else if *self.frozen.get() {
+            return false;
+        }
+
+        // check the missing blocks could be created, as `new_block` would
+        let remaining = (*self.block_allocator.get()).remaining();
+        if remaining.is_none() && self.max_block_count.is_none() {
+            return true;
+        }
+        let mut blocks = self.count_backend_blocks();
+        let mut size = *self.next_block_size.get();
+        let mut needed = 0usize;
+        while missing > 0 {
+            if self.max_block_count.is_some_and(|max| blocks >= max) {
+                return false;
+            }
+            needed = match needed.checked_add(size) {
+                Some(needed) if remaining.is_none_or(|r| needed <= r) => needed,
+                _ => return false,
+            };
+            missing = missing.saturating_sub(size - size_of::<HeapBlock>());
+            blocks += 1;
+            size = self.growth.next_size(size, BS::to_usize());
+        }
+        true
+    }
+
+    /// Create a kernel-compatible layout that can fit the requested layout
+    /// and its registry entry.
+    ///
+    /// Returns `None` if the padded size overflows.
+    fn padded(&self, layout: Layout, align: usize) -> Option<Layout> {
+        LargeRegistry::padded(layout, align)
+    }
+
+    /// Allocate memory as described by `layout`, and tag it.
+    ///
+    /// This is the common implementation of the allocation entry points.
+    unsafe fn alloc_impl(&self, layout: Layout, tag: u8) -> Result<NonNull<u8>, AllocFailure> {
+        let lock = self.mutex.lock().map_err(|_| AllocFailure::Poisoned)?;
+        let ptr = self.alloc_tagged_locked(layout, tag);
+        lock.unlock();
+        self.report_new_blocks();
+        ptr
+    }
+
+    /// Allocate zeroed memory as described by `layout`.
+    ///
+    /// This is the common implementation of the zeroed allocation entry points.
+    unsafe fn alloc_zeroed_impl(&self, layout: Layout) -> Result<NonNull<u8>, AllocFailure> {
+        let lock = self.mutex.lock().map_err(|_| AllocFailure::Poisoned)?;
+        let ptr = self.alloc_tagged_locked(layout, 0);
+        if let Ok(ptr) = ptr {
+            self.zero_locked(ptr.as_ptr(), layout);
+        }
+        lock.unlock();
+        self.report_new_blocks();
+        ptr
+    }
+
+    /// Zero the new allocation at `ptr`, skipping memory known to be zeroed.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn zero_locked(&self, ptr: *mut u8, layout: Layout) {
+        let allocator = &*self.block_allocator.get();
+        let len = match self.block_id_locked(ptr as usize) {
+            // only the header of the hole it was carved from was written to
+            Some(BlockId(base)) if (*(base as *const HeapBlock)).pristine => {
+                min(size_of::<Hole>(), layout.size())
+            }
+            Some(_) => layout.size(),
+            None if allocator.zeroes_blocks() => 0,
+            None => layout.size(),
+        };
+        ptr.write_bytes(0, len);
+    }
+
+    /// Deallocate the memory at `ptr` with the given `layout`.
+    ///
+    /// This is the common implementation of the deallocation entry points.
+    unsafe fn dealloc_impl(&self, ptr: NonNull<u8>, layout: Layout) {
+        // the memory is leaked if the heap may be inconsistent
+        if let Ok(lock) = self.mutex.lock() {
+            self.dealloc_locked(ptr.as_ptr(), layout);
+            lock.unlock();
+        }
+    }
+
+    /// Reallocate the memory at `ptr` from `layout` to `new_layout`.
+    ///
+    /// This is the common implementation of the reallocation entry points.
+    /// The memory at `ptr` is left untouched on error.
+    unsafe fn realloc_impl(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let lock = self.mutex.lock().map_err(|_| AllocError)?;
+        let new_ptr = self.realloc_locked(ptr.as_ptr(), layout, new_layout);
+        lock.unlock();
+        self.report_new_blocks();
+        NonNull::new(new_ptr).ok_or(AllocError)
+    }
+
+    /// Allocate memory as described by `layout`.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn alloc_locked(&self, layout: Layout) -> Result<NonNull<u8>, AllocFailure> {
+        self.alloc_tagged_locked(layout, 0)
+    }
+
+    /// Allocate memory as described by `layout`, and tag it.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn alloc_tagged_locked(
+        &self,
+        layout: Layout,
+        tag: u8,
+    ) -> Result<NonNull<u8>, AllocFailure> {
+        let ptr = self.alloc_untagged_locked(self.tagged(layout))?;
+        self.track(ptr.as_ptr(), layout, tag);
+        self.record(EventKind::Alloc, ptr.as_ptr() as usize, layout.size());
+        Ok(ptr)
+    }
+
+    /// Allocate memory as described by `layout`, including its tag.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn alloc_untagged_locked(&self, layout: Layout) -> Result<NonNull<u8>, AllocFailure> {
+        // if the requested memory block is large, simply dedicate a single block
+        if Self::is_large_size(layout.size()) {
+            return self.alloc_large_locked(layout);
+        }
+
+        // Pad the layout to the minimum legal size
+        let block_layout = match self.block_layout(layout) {
+            Some(block_layout) => block_layout,
+            None => return self.alloc_large_locked(layout),
+        };
+
+        let mut scanned = 0;
+        if let Some(ptr) = self.alloc_in_existing_blocks_locked(block_layout, &mut scanned) {
+            return Ok(NonNull::new_unchecked(ptr));
+        }
+
+        // compact a fragmented heap before growing it, and scan it again
+        if let Some(threshold) = self.compact_threshold {
+            if self.fragmentation_locked() > threshold && self.merge_adjacent_blocks_locked() > 0 {
+                return self.alloc_untagged_locked(layout);
+            }
+        }
+
+        // a new heapblock would not fit the layout either
+        if self.is_large(layout) {
+            return self.alloc_large_locked(layout);
+        }
+
+        // No block can contain the requested layout: allocate a new one !
+        self.alloc_new_block_locked(block_layout, &mut scanned)
+    }
+
+    /// Allocate the padded `block_layout` in one of the current heapblocks.
+    ///
+    /// Returns `None` if no heapblock fits the layout, with the number of
+    /// holes examined in `scanned`. Must be called with the lock held.
+    unsafe fn alloc_in_existing_blocks_locked(
+        &self,
+        block_layout: Layout,
+        scanned: &mut usize,
+    ) -> Option<*mut u8> {
+        // with next-fit, first traverse the heap blocks after the rover
+        let rover = *self.rover.get();
+        if rover != 0 {
+            let mut started = false;
+            let mut block: *mut Option<&mut HeapBlock> = self.first_block.get();
+            while let Some(ref mut b) = *block {
+                started |= *b as *mut HeapBlock as usize == rover;
+                if started {
+                    if let Some(ptr) = self.try_block_locked(b, block_layout, scanned) {
+                        return Some(ptr);
+                    }
+                }
+                block = &mut b.next;
+            }
+        }
+
+        // traverse the heap blocks to find an allocatable block
+        let mut trying = true;
+        let mut next_block: *mut Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut block) = *next_block {
+            trying &= *block as *mut HeapBlock as usize != rover;
+            if trying {
+                if let Some(ptr) = self.try_block_locked(block, block_layout, scanned) {
+                    return Some(ptr);
+                }
+            }
+            next_block = &mut block.next;
+        }
+        None
+    }
+
+    /// Allocate the padded `block_layout` in a new heapblock, linked last.
+    ///
+    /// `scanned` holds the number of holes examined in the current
+    /// heapblocks beforehand. Fails if no new heapblock could be created.
+    /// The layout must fit a fresh heapblock: this is checked in debug
+    /// builds, and the new heapblock is kept in the heap otherwise. Must be
+    /// called with the lock held.
+    unsafe fn alloc_new_block_locked(
+        &self,
+        block_layout: Layout,
+        scanned: &mut usize,
+    ) -> Result<NonNull<u8>, AllocFailure> {
+        let mut next_block: *mut Option<&mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut block) = *next_block {
+            next_block = &mut block.next;
+        }
+
+        // the heap cannot grow
+        let new_block = self.new_block()?;
+
+        // Use the new block to allocate
+        let new_block_ptr = match new_block.allocate_first_fit(block_layout, scanned) {
+            Ok(mem) => mem,
+            Err(error) => {
+                debug_assert!(
+                    false,
+                    "a fresh heapblock cannot fit {:?} ({:?}): large layouts must be checked first",
+                    block_layout, error
+                );
+                self.set_link(next_block, Some(new_block));
+                return Err(AllocFailure::TooLarge);
+            }
+        };
+        if self.next_fit {
+            *self.rover.get() = new_block as *mut HeapBlock as usize;
+        }
+        self.set_link(next_block, Some(new_block));
+        self.count_alloc(block_layout, *scanned, true);
+
+        Ok(new_block_ptr)
+    }
+
+    /// Try to allocate `layout` in `block`, healing it first if enabled.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn try_block_locked(
+        &self,
+        block: &mut HeapBlock,
+        layout: Layout,
+        scanned: &mut usize,
+    ) -> Option<*mut u8> {
+        block.verify();
+        if self.self_heal {
+            if let Some(addr) = block.heal() {
+                self.record(EventKind::Heal, addr, 0);
+            }
+        }
+        let ptr = block.allocate_with::<F>(layout, scanned, usize::MAX).ok()?;
+        self.count_alloc(layout, *scanned, false);
+        if self.next_fit {
+            *self.rover.get() = block as *mut HeapBlock as usize;
+        }
+        Some(ptr.as_ptr())
+    }
+
+    /// Allocate memory as described by `layout` with the underlying allocator.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn alloc_large_locked(&self, layout: Layout) -> Result<NonNull<u8>, AllocFailure> {
+        if LS::to_usize() == 0 {
+            return Err(AllocFailure::TooLarge);
+        } else if *self.frozen.get() {
+            return Err(AllocFailure::NoFit);
+        }
+        let padded = self
+            .padded(layout, LA::to_usize())
+            .ok_or(AllocFailure::TooLarge)?;
+        let allocator = &*self.block_allocator.get();
+        match allocator.allocate(padded) {
+            Ok(ptr) => {
+                *self.large_count.get() += 1;
+                (*self.large_registry.get()).insert(ptr.cast(), padded);
+                #[cfg(feature = "counters")]
+                {
+                    (*self.counters.get()).large_allocations += 1;
+                }
+                Ok(ptr.cast())
+            }
+            Err(_) => Err(AllocFailure::Exhausted),
+        }
+    }
+
+    /// Deallocate the memory at `ptr` with the given `layout`.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn dealloc_locked(&self, ptr: *mut u8, layout: Layout) {
+        self.record(EventKind::Dealloc, ptr as usize, layout.size());
+        self.untrack(ptr, layout);
+        self.dealloc_untagged_locked(ptr, self.tagged(layout));
+    }
+
+    /// Deallocate the memory at `ptr` with the given `layout`, including its tag.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn dealloc_untagged_locked(&self, ptr: *mut u8, layout: Layout) {
+        if (*self.large_registry.get()).contains(ptr as usize) {
+            return self.dealloc_large_locked(ptr);
+        }
+
+        let mut block: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut b) = *block {
+            b.verify();
+            if b.contains(ptr) {
+                let block_layout = self.block_layout(layout).expect("invalid layout");
+                b.deallocate(NonNull::new_unchecked(ptr), block_layout);
+                self.count_dealloc(false);
+                self.release_if_empty(block);
+                return;
+            }
+            block = &mut b.next;
+        }
+        panic!("double free !")
+    }
+
+    /// Release the heapblock at `link` if it is empty and may be released.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn release_if_empty(&self, link: *mut Option<&'static mut HeapBlock>) {
+        if let Some(ref b) = *link {
+            let first = link == self.first_block.get();
+            if b.is_empty()
+                && !self.is_scratch(b)
+                && !(first && self.retain_first_block)
+                && self.count_blocks() > self.min_block_count
+            {
+                self.free_block(link);
+            }
+        }
+    }
+
+    /// Deallocate the registered large allocation at `ptr` with the underlying allocator.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn dealloc_large_locked(&self, ptr: *mut u8) {
+        // replay the layout of the allocation rather than padding the given one
+        let padded = (*self.large_registry.get())
+            .remove(ptr as usize)
+            .expect("double free !");
+        self.free_large_locked(ptr, padded);
+    }
+
+    /// Give the large allocation at `ptr`, made with `padded`, back to the underlying allocator.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn free_large_locked(&self, ptr: *mut u8, padded: Layout) {
+        let allocator = &*self.block_allocator.get();
+        #[cfg(feature = "zero-on-free")]
+        ptr.write_bytes(0, padded.size());
+        allocator.decommit(NonNull::new(ptr).unwrap(), padded.size());
+        allocator.deallocate(NonNull::new(ptr).unwrap(), padded);
+        *self.large_count.get() -= 1;
+        self.count_dealloc(true);
+    }
+
+    /// Reallocate the memory at `ptr` from `layout` to `new_layout`.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn realloc_locked(&self, ptr: *mut u8, layout: Layout, new_layout: Layout) -> *mut u8 {
+        let tag = self.untrack(ptr, layout);
+        let (old, new) = (self.tagged(layout), self.tagged(new_layout));
+
+        // keep the allocation if it has exactly the room needed already,
+        // and if its address satisfies the new alignment
+        if !self.is_large(old)
+            && !self.is_large(new)
+            && self.block_layout(old).map(|l| l.size()) == self.block_layout(new).map(|l| l.size())
+            && (ptr as usize).is_multiple_of(new.align())
+        {
+            self.track(ptr, new_layout, tag);
+            return ptr;
+        }
+
+        // otherwise try to resize it within its heapblock
+        if !self.is_large(old) && !self.is_large(new) && (ptr as usize).is_multiple_of(new.align())
+        {
+            if let (Some(BlockId(base)), Some(size), Some(new_size)) = (
+                self.block_id_locked(ptr as usize),
+                self.block_layout(old).map(|l| l.size()),
+                self.block_layout(new).map(|l| l.size()),
+            ) {
+                let b = &mut *(base as *mut HeapBlock);
+                b.verify();
+                if b.resize_in_place(ptr as usize, size, new_size) {
+                    self.track(ptr, new_layout, tag);
+                    return ptr;
+                }
+            }
+        }
+
+        match self.alloc_tagged_locked(new_layout, tag) {
+            Ok(new_ptr) => {
+                let size = min(layout.size(), new_layout.size());
+                ::core::ptr::copy_nonoverlapping(ptr, new_ptr.as_ptr(), size);
+                self.record(EventKind::Dealloc, ptr as usize, layout.size());
+                self.dealloc_untagged_locked(ptr, old);
+                new_ptr.as_ptr()
+            }
+            Err(_) => {
+                self.track(ptr, layout, tag);
+                ::core::ptr::null_mut::<u8>()
+            }
+        }
+    }
+}
+
+impl<A, BS, BA, LS, LA, F, M> Deblockator<LazyBlocks<A>, BS, BA, LS, LA, F, M>
+where
+    A: BlockAllocator,
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    /// Create a new allocator instance, with an allocator built on first use by `init`.
+    ///
+    /// Unlike [`new`](Self::new), this does not require the underlying
+    /// allocator to be created in a `const` context:
+    /// ```rust,ignore
+    /// #[global_allocator]
+    /// static GLOBAL: Deblockator<LazyBlocks<MyAllocator>> = Deblockator::new_lazy(MyAllocator::open);
+    /// ```
+    pub const fn new_lazy(init: fn() -> A) -> Self {
+        Self::new(LazyBlocks::new(init))
+    }
+}
+
+impl<BS, BA, LS, LA, F, M> Deblockator<NoBlocks, BS, BA, LS, LA, F, M>
+where
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    /// Create a new allocator instance, whose heap is the given region.
+    ///
+    /// The region is used as the one and only heapblock: the heap never
+    /// grows, large layouts cannot be allocated, and allocations fail once
+    /// the region is full. Fails if `region` is not aligned for a heapblock
+    /// header, or too small to hold one and a minimal allocation.
+    pub fn new_in_region(region: &'static mut [u8]) -> Result<Self, AllocError> {
+        let deblockator = Self::new(NoBlocks);
+        let start = region.as_ptr() as usize;
+        let usable = region.len() & !(align_of::<Hole>() - 1);
+        if !start.is_multiple_of(align_of::<HeapBlock>())
+            || usable < size_of::<HeapBlock>() + HeapBlock::<BS>::min_size()
+        {
+            return Err(AllocError);
+        }
+        match deblockator.seed_scratch(region) {
+            true => Ok(deblockator),
+            false => Err(AllocError),
+        }
+    }
+}
+
+unsafe impl<A, BS, BA, LS, LA, F, M> GlobalAlloc for Deblockator<A, BS, BA, LS, LA, F, M>
+where
+    A: BlockAllocator,
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.try_alloc(layout)
+            .map_or(::core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dealloc_impl(NonNull::new_unchecked(ptr), layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_zeroed_impl(layout)
+            .map_or(::core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.realloc_impl(NonNull::new_unchecked(ptr), layout, new_layout)
+            .map_or(::core::ptr::null_mut(), NonNull::as_ptr)
+    }
+}
+
+unsafe impl<A, BS, BA, LS, LA, F, M> Allocator for Deblockator<A, BS, BA, LS, LA, F, M>
+where
+    A: BlockAllocator,
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = unsafe { self.alloc_impl(layout, 0)? };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = unsafe { self.alloc_zeroed_impl(layout)? };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc_impl(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.realloc_impl(ptr, old_layout, new_layout)?;
+        Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()))
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.realloc_impl(ptr, old_layout, new_layout)?;
+        Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()))
+    }
+}
+
+/// Give all heapblocks and large allocations back to the underlying allocator.
+///
+/// This runs before the fields are dropped, so the underlying allocator is
+/// still alive and gets every heapblock back before its own `Drop`. The
+/// scratch buffer, if any, is left untouched. Large allocations are given
+/// back with the layout they were registered with. If the allocator is
+/// poisoned, its heap may be inconsistent and is leaked instead.
+impl<A, BS, BA, LS, LA, F, M> Drop for Deblockator<A, BS, BA, LS, LA, F, M>
+where
+    A: BlockAllocator,
+    BS: Unsigned + 'static,
+    BA: Unsigned + PowerOfTwo,
+    LS: Unsigned,
+    LA: Unsigned + PowerOfTwo,
+    F: FitStrategy,
+    M: RawMutex,
+{
+    fn drop(&mut self) {
+        if self.mutex.is_poisoned() {
+            return;
+        }
+        unsafe {
+            self.release_large();
+            self.release_blocks();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use core::cell::Cell;
+    use core::cell::RefCell;
+
+    use super::super::fit::BestFit;
+
+    use typenum::consts::U0;
+    use typenum::consts::U2048;
+    use typenum::consts::U8192;
+
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+
+    struct MockAlloc {
+        pub allocated: Cell<[bool; 3]>,
+        pub blocks: Box<UnsafeCell<[Page; 3]>>,
+        pub decommitted: Cell<Option<(*mut u8, usize)>>,
+    }
+
+    impl BlockAllocator for MockAlloc {
+        fn remaining(&self) -> Option<usize> {
+            let free = self.allocated.get().iter().filter(|a| !**a).count();
+            Some(free * 4096)
+        }
+
+        unsafe fn decommit(&self, ptr: NonNull<u8>, len: usize) {
+            self.decommitted.set(Some((ptr.as_ptr(), len)));
+        }
+
+        fn owns(&self, ptr: NonNull<u8>) -> Option<bool> {
+            let allocated = self.allocated.get();
+            Some((0..allocated.len()).any(|i| {
+                let block = self.block(i) as usize;
+                allocated[i] && (block..block + 4096).contains(&(ptr.as_ptr() as usize))
+            }))
+        }
+    }
+
+    impl MockAlloc {
+        pub fn new() -> Self {
+            Self {
+                allocated: Cell::new([false; 3]),
+                blocks: Box::new(UnsafeCell::new([
+                    Page([0; 4096]),
+                    Page([0; 4096]),
+                    Page([0; 4096]),
+                ])),
+                decommitted: Cell::new(None),
+            }
+        }
+
+        fn block(&self, i: usize) -> *mut u8 {
+            unsafe { (self.blocks.get() as *mut Page).add(i) as *mut u8 }
+        }
+    }
+
+    unsafe impl Allocator for MockAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() > 4096 || layout.align() > 4096 {
+                return Err(AllocError);
+            }
+            let mut allocated = self.allocated.get();
+            for i in 0..allocated.len() {
+                if !allocated[i] {
+                    allocated[i] = true;
+                    self.allocated.set(allocated);
+                    let ptr = NonNull::new(self.block(i)).ok_or(AllocError)?;
+                    return Ok(NonNull::slice_from_raw_parts(ptr, 4096));
+                }
+            }
+            Err(AllocError)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+            let mut allocated = self.allocated.get();
+            for i in 0..allocated.len() {
+                if ptr.as_ptr() == self.block(i) {
+                    if !allocated[i] {
+                        panic!("double free")
+                    } else {
+                        allocated[i] = false;
+                        self.allocated.set(allocated);
+                        return;
+                    }
+                }
+            }
+            panic!("no such block !")
+        }
+    }
+
+    #[test]
+    /// Test the mock allocator works as expected.
+    fn mockalloc() {
+        unsafe {
+            let ma = MockAlloc::new();
+            let layout = Layout::from_size_align_unchecked(4096, 4096);
+
+            let pt1 = ma.allocate(layout).expect("could not allocate block 1");
+            let _pt2 = ma.allocate(layout).expect("could not allocate block 2");
+            let pt3 = ma.allocate(layout).expect("could not allocate block 3");
+            ma.allocate(layout)
+                .expect_err("all blocks were not allocated");
+
+            assert_eq!(ma.allocated.get(), [true; 3]);
+
+            ma.deallocate(pt1.cast(), layout);
+            assert!(!ma.allocated.get()[0]);
+
+            ma.deallocate(pt3.cast(), layout);
+            assert!(!ma.allocated.get()[2]);
+
+            let pt4 = ma.allocate(layout).expect("could not allocate block 4");
+            assert!(ma.allocated.get()[0]);
+            assert!(!ma.allocated.get()[2]);
+            assert_eq!(pt4.cast::<u8>(), pt1.cast::<u8>());
+        }
+    }
+
+    #[test]
+    /// Check the underlying blocks are allocated as expected.
+    fn deblockator_blocks() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+
+        unsafe {
+            // quick accessor to the allocated blocks
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            // check the hole lists of all the heapblocks
+            let validate = || {
+                let mut block: *const Option<&mut HeapBlock> = va.first_block.get();
+                while let Some(ref b) = *block {
+                    b.validate().expect("corrupted hole list");
+                    block = &b.next;
+                }
+            };
+
+            // Allocate a single boxed u32
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptr1 = NonNull::new(va.alloc(layout)).expect("could not allocate 1");
+            ::core::ptr::write(ptr1.as_ptr(), 255);
+            assert_eq!(allocated(), [true, false, false]);
+            validate();
+
+            // Allocate a second boxed u32
+            let ptr2 = NonNull::new(va.alloc(layout)).expect("could not allocate 2");
+            ::core::ptr::write(ptr2.as_ptr(), 254);
+            assert_eq!(allocated(), [true, false, false]);
+            validate();
+
+            // Allocate a large object to the second block
+            let layout = Layout::from_size_align(3129, 4096).expect("bad layout");
+            let ptr3 = NonNull::new(va.alloc(layout)).expect("could not allocate 3");
+            assert_eq!(allocated(), [true, true, false]);
+            validate();
+
+            // Deallocate the first u32
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            va.dealloc(ptr1.as_ptr(), layout);
+            validate();
+
+            // FIXME: Reallocate the first u32 (hopefully at the same place)
+            let ptr4 = NonNull::new(va.alloc(layout)).expect("could not allocate 4");
+            assert_eq!(ptr4.as_ptr(), ptr1.as_ptr());
+            validate();
 
             // Deallocate the large block
             let layout = Layout::from_size_align(3129, 4096).expect("bad layout");
             va.dealloc(ptr3.as_ptr(), layout);
             assert_eq!(allocated(), [true, false, false]);
+            validate();
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "event-log")]
+    /// Check the event log records operations in order.
+    fn deblockator_recent_events() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+
+        unsafe {
+            let small = Layout::from_size_align(32, 8).expect("bad layout");
+            let large = Layout::from_size_align(3129, 4096).expect("bad layout");
+
+            let ptr1 = va.alloc(small);
+            let ptr2 = va.alloc(large);
+            va.dealloc(ptr1, small);
+            va.dealloc(ptr2, large);
+
+            let block = (*va.first_block.get()).as_ref().unwrap();
+            let base = *block as *const HeapBlock as usize;
+
+            let mut events = [Event::default(); 8];
+            assert_eq!(va.recent_events(&mut events), 5);
+            assert_eq!(
+                &events[..5],
+                &[
+                    Event {
+                        kind: EventKind::BlockCreate,
+                        addr: base,
+                        size: 4096
+                    },
+                    Event {
+                        kind: EventKind::Alloc,
+                        addr: ptr1 as usize,
+                        size: 32
+                    },
+                    Event {
+                        kind: EventKind::Alloc,
+                        addr: ptr2 as usize,
+                        size: 3129
+                    },
+                    Event {
+                        kind: EventKind::Dealloc,
+                        addr: ptr1 as usize,
+                        size: 32
+                    },
+                    Event {
+                        kind: EventKind::Dealloc,
+                        addr: ptr2 as usize,
+                        size: 3129
+                    },
+                ]
+            );
+
+            // a smaller buffer only receives the most recent events
+            let mut events = [Event::default(); 2];
+            assert_eq!(va.recent_events(&mut events), 2);
+            assert_eq!(events[0].kind, EventKind::Dealloc);
+            assert_eq!(events[0].addr, ptr1 as usize);
+            assert_eq!(events[1].addr, ptr2 as usize);
+        }
+    }
+
+    #[test]
+    /// Check a small layout too aligned for a heapblock uses the large path.
+    fn deblockator_overaligned_small() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+
+            // the block header prevents aligning anything to the block size
+            let layout = Layout::from_size_align(2040, 4096).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 4096, 0);
+            assert!((*va.first_block.get()).is_none());
+            assert_eq!(allocated(), [true, false, false]);
+
+            va.dealloc(ptr, layout);
+            assert_eq!(allocated(), [false, false, false]);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "tags"))] // tags change the size of allocations
+    /// Check reservations are predicted from the free and remaining space.
+    fn deblockator_try_reserve() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+        let capacity = HeapBlock::<U4096>::capacity();
+
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+
+            // all the blocks of the mock can be used
+            assert!(va.try_reserve(0));
+            assert!(va.try_reserve(3 * capacity));
+            assert!(!va.try_reserve(3 * capacity + 1));
+            assert!(!va.try_reserve(usize::MAX));
+            assert_eq!(allocated(), [false, false, false]);
+
+            // free space in existing blocks is taken into account
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!(va.try_reserve(3 * capacity - 32));
+            assert!(!va.try_reserve(3 * capacity - 31));
+            assert_eq!(allocated(), [true, false, false]);
+
+            va.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "tags"))] // tags change the size of allocations
+    /// Check reservations needing new heapblocks honour the freeze and the block count cap.
+    fn deblockator_try_reserve_limits() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).max_block_count(2);
+        let capacity = HeapBlock::<U4096>::capacity();
+
+        unsafe {
+            assert!(va.try_reserve(2 * capacity));
+            assert!(!va.try_reserve(2 * capacity + 1));
+
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!(va.try_reserve(2 * capacity - 32));
+            assert!(!va.try_reserve(2 * capacity - 31));
+
+            // the free space left is still available once frozen
+            va.freeze();
+            assert!(va.try_reserve(capacity - 32));
+            assert!(!va.try_reserve(capacity - 31));
+            va.unfreeze();
+            assert!(va.try_reserve(capacity - 31));
+
+            va.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "tags"))] // tags change the size of allocations
+    /// Check reservations are predicted with the heapblock sizes of the growth policy.
+    fn deblockator_try_reserve_growth() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).growth_policy(GrowthPolicy::Geometric {
+                factor: 2,
+                max: 8192,
+            });
+        // the mock only has room for a single 4096 and a single 8192 heapblock
+        assert!(va.try_reserve(4096 + 8192 - 2 * size_of::<HeapBlock>()));
+        assert!(!va.try_reserve(4096 + 8192 - 2 * size_of::<HeapBlock>() + 1));
+    }
+
+    #[test]
+    #[cfg(feature = "counters")]
+    /// Check the average scan length grows with fragmentation.
+    fn deblockator_avg_scan_length() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+        assert_eq!(va.avg_scan_length(), 0.0);
+
+        unsafe {
+            // on a clean heap, the first hole always fits
+            let small = Layout::from_size_align(32, 8).expect("bad layout");
+            let mut ptrs = [core::ptr::null_mut(); 20];
+            for ptr in ptrs.iter_mut() {
+                *ptr = va.alloc(small);
+            }
+            let clean = va.avg_scan_length();
+            assert_eq!(clean, 1.0);
+
+            // free every other allocation, leaving small holes behind
+            for ptr in ptrs.iter().step_by(2) {
+                va.dealloc(*ptr, small);
+            }
+
+            // larger allocations now have to skip the small holes
+            let medium = Layout::from_size_align(64, 8).expect("bad layout");
+            for _ in 0..5 {
+                va.alloc(medium);
+            }
+            assert!(va.avg_scan_length() > clean);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "counters")]
+    /// Check reallocating within a size class is counted as reuse.
+    fn deblockator_class_stats() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).expect("bad layout");
+            let class = size_class(va.block_layout(va.tagged(layout)).unwrap().size());
+            let ptr = va.alloc(layout);
+            assert_eq!(
+                va.class_stats()[class],
+                ClassStats {
+                    reused: 0,
+                    grown: 1
+                }
+            );
+            va.dealloc(ptr, layout);
+
+            let ptr = va.alloc(layout);
+            assert_eq!(
+                va.class_stats()[class],
+                ClassStats {
+                    reused: 1,
+                    grown: 1
+                }
+            );
+            assert_eq!(va.class_stats()[class].reuse_rate(), 0.5);
+            va.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    /// Check freed large blocks are decommitted before being deallocated.
+    fn deblockator_decommit() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+
+        unsafe {
+            let decommitted = || (*va.block_allocator.get()).decommitted.get();
+
+            // small allocations are never decommitted
+            let small = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptr1 = va.alloc(small);
+            va.dealloc(ptr1, small);
+            assert_eq!(decommitted(), None);
+
+            let large = Layout::from_size_align(3129, 8).expect("bad layout");
+            let ptr2 = va.alloc(large);
+            assert_eq!(decommitted(), None);
+            va.dealloc(ptr2, large);
+            assert_eq!(decommitted(), Some((ptr2, 4096)));
+        }
+    }
+
+    #[test]
+    /// Check blocks are reserved when creating an allocator with a capacity.
+    fn deblockator_with_capacity() {
+        type Va = Deblockator<MockAlloc, U4096, U4096, U2048, U4096>;
+
+        let va = Va::with_capacity(MockAlloc::new(), 5000).expect("could not reserve");
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            assert_eq!(allocated(), [true, true, false]);
+
+            // the first allocation uses the reserved memory
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(allocated(), [true, true, false]);
+            let block = (*va.first_block.get()).as_ref().unwrap();
+            assert!(block.contains(ptr));
+        }
+
+        assert!(Va::with_capacity(MockAlloc::new(), 0).is_ok());
+        assert!(Va::with_capacity(MockAlloc::new(), 20000).is_err());
+    }
+
+    #[test]
+    /// Check a panic while the lock is held poisons the allocator.
+    fn deblockator_poisoned() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+        let layout = Layout::from_size_align(32, 8).expect("bad layout");
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _lock = va.mutex.lock().expect("could not lock");
+            panic!("panic while allocating");
+        }));
+        assert!(result.is_err());
+        assert!(va.is_poisoned());
+
+        unsafe {
+            // a poisoned allocator refuses to allocate
+            assert!(va.mutex.lock().is_err());
+            assert!(va.alloc(layout).is_null());
+            assert!(!va.try_reserve(0));
+
+            // allocation works again once the poison is cleared
+            va.clear_poison();
+            let ptr = va.alloc(layout);
+            assert!(!ptr.is_null());
+            va.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "tags"))] // tags change the size of allocations
+    /// Check batch allocations are contiguous when a block has room.
+    fn deblockator_alloc_batch() {
+        let ma = MockAlloc::new();
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> = Deblockator::new(ma);
+
+        unsafe {
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            let mut ptrs = [core::ptr::null_mut(); 12];
+            assert_eq!(va.alloc_batch(layout, 10, &mut ptrs), 10);
+            for i in 1..10 {
+                assert_eq!(ptrs[i] as usize - ptrs[i - 1] as usize, 32);
+            }
+            assert!(ptrs[10].is_null());
+
+            // each allocation can be freed on its own
+            for ptr in &ptrs[..10] {
+                va.dealloc(*ptr, layout);
+            }
+            let block = (*va.first_block.get()).as_ref().unwrap();
+            assert_eq!(block.free_bytes(), HeapBlock::<U4096>::capacity());
+
+            // batches larger than a block span several heapblocks
+            let layout = Layout::from_size_align(512, 8).expect("bad layout");
+            let mut ptrs = [core::ptr::null_mut(); 16];
+            assert_eq!(va.alloc_batch(layout, 16, &mut ptrs), 16);
+            assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+            assert_eq!((*va.block_allocator.get()).allocated.get(), [true; 3]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "counters")]
+    /// Check every allocation of a batch is accounted, and fully given back.
+    fn deblockator_alloc_batch_accounting() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).over_allocate(true);
+
+        unsafe {
+            let layout = Layout::from_size_align(24, 8).expect("bad layout");
+            let mut ptrs = [core::ptr::null_mut(); 10];
+            assert_eq!(va.alloc_batch(layout, 10, &mut ptrs), 10);
+            assert_eq!(va.small_alloc_count(), 10);
+            for ptr in &ptrs {
+                va.dealloc(*ptr, layout);
+            }
+            assert_eq!(va.small_dealloc_count(), 10);
+        }
+
+        let stats = va.stats();
+        assert_eq!(stats.used_bytes, size_of::<HeapBlock>());
+        assert_eq!(stats.largest_free_block, HeapBlock::<U4096>::capacity());
+    }
+
+    #[test]
+    #[cfg(not(feature = "tags"))] // tags change the size of allocations
+    /// Check over-allocated memory can grow without moving.
+    fn deblockator_over_allocate() {
+        type Va = Deblockator<MockAlloc, U4096, U4096, U2048, U4096>;
+
+        unsafe {
+            let layout = Layout::from_size_align(20, 4).expect("bad layout");
+
+            // without over-allocation, a 20 bytes allocation only holds 24 bytes
+            let va = Va::new(MockAlloc::new());
+            let ptr = va.alloc(layout);
+            // keep the allocation from growing in place
+            va.alloc(layout);
+            assert_eq!(va.realloc(ptr, layout, 24), ptr);
+            let layout24 = Layout::from_size_align(24, 4).expect("bad layout");
+            assert_ne!(va.realloc(ptr, layout24, 28), ptr);
+
+            // with over-allocation, it holds 32 bytes
+            let va = Va::new(MockAlloc::new()).over_allocate(true);
+            let ptr = va.alloc(layout);
+            va.alloc(layout);
+            let mut current = layout;
+            for size in [24, 28, 32] {
+                assert_eq!(va.realloc(ptr, current, size), ptr);
+                current = Layout::from_size_align(size, 4).expect("bad layout");
+            }
+            let moved = va.realloc(ptr, current, 33);
+            assert_ne!(moved, ptr);
+
+            // the data is preserved when moving
+            assert!(!moved.is_null());
+            ::core::ptr::write_bytes(moved, 0xAB, 33);
+            let layout33 = Layout::from_size_align(33, 4).expect("bad layout");
+            let shrunk = va.realloc(moved, layout33, 16);
+            assert_eq!(*shrunk.add(15), 0xAB);
+        }
+    }
+
+    #[test]
+    /// Check words pointing into live allocations are reported.
+    fn deblockator_scan_for_references() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let live = va.alloc(layout) as usize;
+            let freed = va.alloc(layout) as usize;
+            va.dealloc(freed as *mut u8, layout);
+
+            let header = va.block_allocator.get().as_ref().unwrap().block(0) as usize;
+            let region = [0, live, 12345, freed, live + 63, header, live + 8];
+
+            let mut found = Vec::new();
+            va.scan_for_references(&region, |word| found.push(word));
+            assert_eq!(found, [live, live + 63, live + 8]);
+        }
+    }
+
+    #[test]
+    /// Check the new block hook is called for every new heapblock.
+    fn deblockator_new_block_hook() {
+        static BLOCKS: std::sync::Mutex<Vec<(usize, usize)>> = std::sync::Mutex::new(Vec::new());
+        fn hook(base: usize, size: usize) {
+            BLOCKS.lock().unwrap().push((base, size));
+        }
+
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        va.set_new_block_hook(hook);
+        let blocks = |i| unsafe { va.block_allocator.get().as_ref().unwrap().block(i) as usize };
+
+        unsafe {
+            let layout = Layout::from_size_align(1024, 16).expect("bad layout");
+            va.alloc(layout);
+            assert_eq!(*BLOCKS.lock().unwrap(), [(blocks(0), 4096)]);
+            va.alloc(layout);
+            va.alloc(layout);
+            assert_eq!(BLOCKS.lock().unwrap().len(), 1);
+
+            // large allocations do not create heapblocks
+            let large = Layout::from_size_align(2048, 16).expect("bad layout");
+            va.alloc(large);
+            assert_eq!(BLOCKS.lock().unwrap().len(), 1);
+
+            va.alloc(layout);
+            assert_eq!(
+                *BLOCKS.lock().unwrap(),
+                [(blocks(0), 4096), (blocks(2), 4096)]
+            );
+        }
+    }
+
+    #[test]
+    /// Check large layouts are padded to the backend size and alignment.
+    fn deblockator_padded() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        let cases = [
+            ((3000, 16), (4096, 4096)),
+            ((5000, 4096), (8192, 4096)),
+            ((3000, 8192), (4096, 8192)),
+            ((9000, 16384), (12288, 16384)),
+        ];
+        for ((size, align), (padded_size, padded_align)) in cases {
+            let layout = Layout::from_size_align(size, align).expect("bad layout");
+            let padded = va.padded(layout, 4096).expect("padded size overflows");
+            assert_eq!(padded.size(), padded_size);
+            assert_eq!(padded.align(), padded_align);
+        }
+    }
+
+    #[test]
+    /// Check layouts whose padded size overflows are not allocated.
+    fn deblockator_padded_overflow() {
+        let huge = Layout::from_size_align(isize::MAX as usize - 4000, 8).expect("bad layout");
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        assert_eq!(va.padded(huge, 4096), None);
+        unsafe {
+            assert!(va.alloc(huge).is_null());
+            assert!(va.alloc_zeroed(huge).is_null());
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!(va.realloc(ptr, layout, huge.size()).is_null());
+            va.dealloc(ptr, layout);
+            assert_eq!(
+                (*va.block_allocator.get()).allocated.get(),
+                [true, false, false]
+            );
+        }
+
+        // without a large size, the heapblock path must fail as well
+        let va: Deblockator<MockAlloc, U4096, U4096, U0, U4096> =
+            Deblockator::new(MockAlloc::new()).over_allocate(true);
+        assert_eq!(va.block_layout(huge), None);
+        assert!(unsafe { va.alloc(huge) }.is_null());
+        assert!(va.would_grow(huge));
+    }
+
+    #[test]
+    /// Check allocations are visited in increasing address order.
+    fn deblockator_foreach_allocation_ordered() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let layout = Layout::from_size_align(1024, 16).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            let c = va.alloc(layout);
+            let d = va.alloc(layout);
+            va.dealloc(b, layout);
+
+            let mut spans = Vec::new();
+            va.foreach_allocation_ordered(|ptr, size| spans.push((ptr, size)));
+            let size = va.block_layout(va.tagged(layout)).unwrap().size();
+            assert_eq!(spans, [(a, size), (c, size), (d, size)]);
+            assert!(spans.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+    }
+
+    #[test]
+    /// Check empty heapblocks are freed, except for the first one if retained.
+    fn deblockator_retain_first_block() {
+        type Va = Deblockator<MockAlloc, U4096, U4096, U2048, U4096>;
+        let layout = Layout::from_size_align(1024, 16).expect("bad layout");
+
+        unsafe {
+            let va = Va::new(MockAlloc::new());
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            let ptrs: Vec<_> = (0..4).map(|_| va.alloc(layout)).collect();
+            assert_eq!(allocated(), [true, true, false]);
+
+            va.dealloc(ptrs[3], layout);
+            assert_eq!(allocated(), [true, false, false]);
+            for &ptr in &ptrs[..3] {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(allocated(), [true, false, false]);
+            assert!((*va.first_block.get()).is_some());
+
+            let va = Va::new(MockAlloc::new()).retain_first_block(false);
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            let ptrs: Vec<_> = (0..4).map(|_| va.alloc(layout)).collect();
+            for &ptr in &ptrs {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(allocated(), [false, false, false]);
+            assert!((*va.first_block.get()).is_none());
+        }
+    }
+
+    #[test]
+    /// Check whether a new heapblock is needed is predicted correctly.
+    fn deblockator_would_grow() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let allocated = || unsafe { (*va.block_allocator.get()).allocated.get() };
+
+        unsafe {
+            let small = Layout::from_size_align(1024, 16).expect("bad layout");
+            let large = Layout::from_size_align(2048, 16).expect("bad layout");
+
+            // an empty heap always needs a new heapblock
+            assert!(va.would_grow(small));
+            let ptrs = [va.alloc(small), va.alloc(small), va.alloc(small)];
+
+            // the remaining hole is too small for another allocation
+            assert!(va.would_grow(small));
+            assert!(!va.would_grow(Layout::from_size_align(512, 16).unwrap()));
+            assert!(va.would_grow(large));
+
+            // freeing some memory leaves a fitting hole
+            va.dealloc(ptrs[1], small);
+            assert!(!va.would_grow(small));
+            assert_eq!(allocated(), [true, false, false]);
+            assert_eq!(va.alloc(small), ptrs[1]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tags")]
+    /// Check the allocated bytes are accounted per tag.
+    fn deblockator_alloc_tagged() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let small = Layout::from_size_align(100, 8).expect("bad layout");
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+
+            let gfx1 = va.alloc_tagged(small, 1);
+            let gfx2 = va.alloc_tagged(large, 1);
+            let audio = va.alloc_tagged(small, 2);
+            let other = va.alloc(small);
+            let used = va.used_bytes_by_tag();
+            assert_eq!((used[0], used[1], used[2]), (100, 3100, 100));
+
+            // reallocation keeps the tag
+            let audio = va.realloc(audio, small, 150);
+            let audio_large = Layout::from_size_align(150, 8).expect("bad layout");
+            let used = va.used_bytes_by_tag();
+            assert_eq!((used[0], used[1], used[2]), (100, 3100, 150));
+
+            va.dealloc(gfx1, small);
+            va.dealloc(gfx2, large);
+            va.dealloc(audio, audio_large);
+            va.dealloc(other, small);
+            assert_eq!(va.used_bytes_by_tag(), [0; 256]);
+        }
+    }
+
+    #[test]
+    /// Check empty heapblocks are kept up to the minimum block count.
+    fn deblockator_min_block_count() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).min_block_count(2);
+        let allocated = || unsafe { (*va.block_allocator.get()).allocated.get() };
+
+        unsafe {
+            let layout = Layout::from_size_align(2040, 16).expect("bad layout");
+            let ptrs = [va.alloc(layout), va.alloc(layout), va.alloc(layout)];
+            assert_eq!(allocated(), [true, true, true]);
+            for ptr in ptrs {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(allocated(), [true, false, true]);
+        }
+    }
+
+    #[test]
+    /// Check trimming frees empty heapblocks down to the target.
+    fn deblockator_trim() {
+        type Va = Deblockator<MockAlloc, U4096, U4096, U2048, U4096>;
+        let layout = Layout::from_size_align(1024, 16).expect("bad layout");
+
+        unsafe {
+            let va = Va::with_capacity(MockAlloc::new(), 10000).expect("could not reserve");
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            assert_eq!(allocated(), [true, true, true]);
+            let ptr = va.alloc(layout);
+
+            // the first heapblock is in use
+            assert_eq!(va.trim(0), 2 * 4096);
+            assert_eq!(allocated(), [true, false, false]);
+            va.dealloc(ptr, layout);
+            assert_eq!(va.trim(4096), 0);
+            assert_eq!(va.trim(0), 4096);
+            assert_eq!(allocated(), [false, false, false]);
+
+            let va = Va::with_capacity(MockAlloc::new(), 10000)
+                .expect("could not reserve")
+                .min_block_count(2);
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            assert_eq!(va.trim(0), 4096);
+            assert_eq!(allocated(), [false, true, true]);
+        }
+    }
+
+    #[test]
+    /// Check a corrupted hole link is dropped instead of followed.
+    fn deblockator_self_heal() {
+        // a link below the heap, and one whose hole would wrap around the address space
+        for link in [0xDEAD_BEE8, usize::MAX & !7] {
+            self_heal_link(link);
+        }
+    }
+
+    /// Check the heap survives the link of a free hole being overwritten with `link`.
+    fn self_heal_link(link: usize) {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).self_heal(true);
+
+        unsafe {
+            let layout = Layout::from_size_align(1024, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            va.dealloc(a, layout);
+
+            // overwrite the link from the freed hole to the last hole
+            let hole = &mut *(a as *mut Hole);
+            assert!(hole.next.is_some());
+            (&mut hole.next as *mut Option<&mut Hole> as *mut usize).write(link);
+
+            // the freed hole can still be used, the rest of the block is lost
+            let small = Layout::from_size_align(64, 8).expect("bad layout");
+            assert_eq!(va.alloc(small), a);
+            let c = va.alloc(layout);
+            assert!(!c.is_null());
+            assert!(!(*va.first_block.get()).as_ref().unwrap().contains(c));
+            va.dealloc(b, layout);
+
+            #[cfg(feature = "event-log")]
+            {
+                let mut events = [Event::default(); 16];
+                let count = va.recent_events(&mut events);
+                assert!(events[..count]
+                    .iter()
+                    .any(|event| event.kind == EventKind::Heal && event.addr == link));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "counters")]
+    /// Check quick statistics can be read while allocating from another thread.
+    fn deblockator_quick_stats() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
+
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let layout = Layout::from_size_align(100, 8).expect("bad layout");
+                for _ in 0..10000 {
+                    unsafe {
+                        let ptrs = [va.alloc(layout), va.alloc(layout)];
+                        for ptr in ptrs {
+                            va.dealloc(ptr, layout);
+                        }
+                    }
+                }
+                done.store(true, Ordering::Release);
+            });
+            while !done.load(Ordering::Acquire) {
+                let stats = va.quick_stats();
+                assert!(stats.used_bytes <= 200);
+                assert!(stats.block_count <= 1);
+            }
+        });
+
+        let stats = va.quick_stats();
+        assert_eq!(stats.used_bytes, 0);
+        assert_eq!(stats.block_count, 1);
+    }
+
+    #[test]
+    /// Check an allocation filling a whole heapblock is served from a single block.
+    fn deblockator_full_block() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U4096, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let allocated = || unsafe { (*va.block_allocator.get()).allocated.get() };
+
+        unsafe {
+            // leave room for the allocation tag, if any
+            let overhead = va.tagged(Layout::new::<u8>()).size() - 1;
+            let size = HeapBlock::<U4096>::capacity() - overhead;
+            let layout = Layout::from_size_align(size, 8).expect("bad layout");
+
+            let ptr = va.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(allocated(), [true, false, false]);
+            let block = (*va.first_block.get()).as_ref().unwrap();
+            assert_eq!(
+                ptr as usize,
+                *block as *const HeapBlock as usize + size_of::<HeapBlock>()
+            );
+            assert_eq!(block.free_bytes(), 0);
+
+            // the next allocation needs a new heapblock
+            let tiny = Layout::from_size_align(1, 1).expect("bad layout");
+            let next = va.alloc(tiny);
+            assert!(!next.is_null());
+            assert_eq!(allocated(), [true, true, false]);
+
+            // one more byte does not fit in a heapblock at all
+            let layout_plus = Layout::from_size_align(size + 1, 8).expect("bad layout");
+            assert!(va.is_large(va.tagged(layout_plus)));
+
+            va.dealloc(ptr, layout);
+            va.dealloc(next, tiny);
+            assert_eq!(allocated(), [true, false, false]);
+            let block = (*va.first_block.get()).as_ref().unwrap();
+            assert_eq!(block.free_bytes(), HeapBlock::<U4096>::capacity());
+        }
+    }
+
+    #[test]
+    /// Check heapblocks are described in address order.
+    fn deblockator_iter_blocks_ordered() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            // get the second block allocated before the first one
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let small = Layout::from_size_align(2040, 8).expect("bad layout");
+            let ptr = va.alloc(large);
+            va.alloc(small);
+            va.dealloc(ptr, large);
+            va.alloc(small);
+            let first = (*va.first_block.get()).as_ref().unwrap();
+            assert_eq!(*first as *const HeapBlock as *mut u8, mock.block(1));
+
+            let mut infos = [BlockInfo::default(); 3];
+            assert_eq!(va.iter_blocks_ordered(&mut infos), 2);
+            assert_eq!(infos[0].base, mock.block(0) as usize);
+            assert_eq!(infos[1].base, mock.block(1) as usize);
+            for info in &infos[..2] {
+                assert_eq!(info.size, 4096);
+                assert_eq!(info.holes, 1);
+                assert_eq!(info.free_bytes, info.largest_hole);
+            }
+
+            let mut infos = [BlockInfo::default(); 1];
+            assert_eq!(va.iter_blocks_ordered(&mut infos), 1);
+            assert_eq!(infos[0].base, mock.block(0) as usize);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "hardened")]
+    #[should_panic(expected = "heap block header corrupted")]
+    /// Check a clobbered heapblock header is detected.
+    fn deblockator_hardened() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let layout = Layout::from_size_align(2040, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            va.dealloc(a, layout);
+            va.dealloc(b, layout);
+            va.alloc(layout);
+
+            // overwrite the hole list head of the first heapblock
+            let block = (*va.first_block.get()).as_mut().unwrap();
+            block.first.next = None;
+            va.alloc(layout);
+        }
+    }
+
+    #[test]
+    /// Check reallocating with a stricter alignment moves the memory if needed.
+    fn deblockator_realloc_aligned() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!(!(ptr as usize).is_multiple_of(64));
+            ::core::ptr::write_bytes(ptr, 0x42, 64);
+
+            // the current address is not aligned enough
+            let aligned = Layout::from_size_align(64, 64).expect("bad layout");
+            let moved = va.realloc_aligned(ptr, layout, aligned);
+            assert!((moved as usize).is_multiple_of(64));
+            assert_eq!(*moved.add(63), 0x42);
+
+            // the current address is already aligned enough
+            assert_eq!(va.realloc_aligned(moved, aligned, layout), moved);
+            va.dealloc(moved, layout);
+        }
+    }
+
+    #[test]
+    /// Check the pressure level follows the heapblock utilization.
+    fn deblockator_pressure() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).pressure_thresholds(40, 70);
+
+        unsafe {
+            let layout = Layout::from_size_align(1024, 8).expect("bad layout");
+            assert_eq!(va.pressure(), Pressure::Low);
+            let a = va.alloc(layout);
+            assert_eq!(va.pressure(), Pressure::Low);
+            let b = va.alloc(layout);
+            assert_eq!(va.pressure(), Pressure::Medium);
+            let c = va.alloc(layout);
+            assert_eq!(va.pressure(), Pressure::High);
+
+            // the hole left in the middle is not enough to relieve the heap
+            va.dealloc(b, layout);
+            assert_eq!(va.pressure(), Pressure::High);
+            va.dealloc(c, layout);
+            assert_eq!(va.pressure(), Pressure::Low);
+            va.dealloc(a, layout);
+            assert_eq!(va.pressure(), Pressure::Low);
+        }
+    }
+
+    #[test]
+    /// Check the heapblock with the most scattered free space is reported.
+    fn deblockator_most_fragmented_block() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+        assert_eq!(va.most_fragmented_block(), None);
+
+        unsafe {
+            // leave 3 holes of the same size in the first heapblock
+            let small = Layout::from_size_align(512, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..7).map(|_| va.alloc(small)).collect();
+            for ptr in ptrs.iter().skip(1).step_by(2) {
+                va.dealloc(*ptr, small);
+            }
+
+            // leave a single hole before the tail of the second heapblock
+            let medium = Layout::from_size_align(1024, 8).expect("bad layout");
+            let ptr = va.alloc(medium);
+            va.alloc(medium);
+            va.dealloc(ptr, medium);
+            assert_eq!(
+                (*va.block_allocator.get()).allocated.get(),
+                [true, true, false]
+            );
+
+            let (base, fragmentation) = va.most_fragmented_block().unwrap();
+            assert_eq!(base, mock.block(0) as usize);
+            assert!(fragmentation > 0.7);
+        }
+    }
+
+    #[test]
+    /// Check a lazy allocator is only built by the first allocation.
+    fn deblockator_new_lazy() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        static BUILT: AtomicUsize = AtomicUsize::new(0);
+        fn init() -> MockAlloc {
+            BUILT.fetch_add(1, Ordering::SeqCst);
+            MockAlloc::new()
+        }
+
+        let va: Deblockator<LazyBlocks<MockAlloc>, U4096, U4096, U2048, U4096> =
+            Deblockator::new_lazy(init);
+        assert!(va.try_reserve(100));
+        assert_eq!(BUILT.load(Ordering::SeqCst), 0);
+
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            assert_eq!(BUILT.load(Ordering::SeqCst), 1);
+            let b = va.alloc(layout);
+            assert_eq!(BUILT.load(Ordering::SeqCst), 1);
+
+            let mock = (*va.block_allocator.get()).get().unwrap();
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+            va.dealloc(a, layout);
+            va.dealloc(b, layout);
+        }
+    }
+
+    #[test]
+    /// Check contiguous heapblocks are merged and serve larger allocations.
+    fn deblockator_merge_adjacent_blocks() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U8192, U4096> =
+            Deblockator::with_capacity(MockAlloc::new(), 5000).expect("out of memory");
+        let mock = unsafe { &*va.block_allocator.get() };
+        assert_eq!(mock.allocated.get(), [true, true, false]);
+        assert_eq!(va.merge_adjacent_blocks(), 1);
+        assert_eq!(va.merge_adjacent_blocks(), 0);
+
+        unsafe {
+            // too large for a single block, but not for the merged heapblock
+            let layout = Layout::from_size_align(6000, 8).expect("bad layout");
+            assert!(!va.would_grow(layout));
+            let ptr = va.alloc(layout);
+            assert!(ptr as usize > mock.block(0) as usize);
+            assert!((ptr as usize + 6000) <= mock.block(2) as usize);
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+            va.dealloc(ptr, layout);
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "poison")]
+    /// Check writing to deallocated memory is detected.
+    fn deblockator_check_poison() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        assert_eq!(va.check_poison(), Ok(()));
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            va.dealloc(a, layout);
+            assert_eq!(va.check_poison(), Ok(()));
+
+            // write past the header of the hole
+            a.add(32).write(0x42);
+            assert_eq!(va.check_poison(), Err(a));
+
+            // reallocating the memory clears the corruption
+            assert_eq!(va.alloc(layout), a);
+            assert_eq!(va.check_poison(), Ok(()));
+            va.dealloc(a, layout);
+            va.dealloc(b, layout);
+            assert_eq!(va.check_poison(), Ok(()));
+        }
+    }
+
+    #[test]
+    /// Check deallocating with a block hint does not traverse the heapblocks.
+    fn deblockator_dealloc_in_block() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..10).map(|_| va.alloc(layout)).collect();
+            let hint = va.block_id(ptrs[0]).expect("not in a heapblock");
+            assert_eq!(hint, BlockId(mock.block(0) as usize));
+            assert_eq!(va.block_id(mock.block(2)), None);
+
+            // fill the first heapblock so that a second one is needed
+            let large = Layout::from_size_align(2000, 8).expect("bad layout");
+            let a = va.alloc(large);
+            let b = va.alloc(large);
+            let other = va.block_id(b).expect("not in a heapblock");
+            assert_eq!(other, BlockId(mock.block(1) as usize));
+
+            // detach the heapblocks: a traversal would not find any pointer
+            let blocks = (*va.first_block.get()).take();
+            for ptr in &ptrs[1..] {
+                va.dealloc_in_block(*ptr, layout, hint);
+            }
+            *va.first_block.get() = blocks;
+
+            // a wrong hint falls back to a traversal
+            va.dealloc_in_block(ptrs[0], layout, other);
+            va.dealloc_in_block(a, large, other);
+            va.dealloc_in_block(b, large, other);
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+            let info = (*va.first_block.get()).as_ref().unwrap().info();
+            assert_eq!(info.holes, 1);
+        }
+    }
+
+    #[test]
+    /// Check `GlobalAlloc` fails exactly when `Allocator` fails.
+    fn deblockator_allocator_matches_globalalloc() {
+        let global: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let allocator: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        let layouts = [
+            Layout::from_size_align(16, 8).expect("bad layout"),
+            Layout::from_size_align(3000, 8).expect("bad layout"),
+            Layout::from_size_align(5000, 8).expect("bad layout"),
+            Layout::from_size_align(1000, 8192).expect("bad layout"),
+            Layout::from_size_align(1000, 8).expect("bad layout"),
+            Layout::from_size_align(3000, 8).expect("bad layout"),
+            Layout::from_size_align(3000, 8).expect("bad layout"),
+        ];
+
+        unsafe {
+            for layout in layouts {
+                let ptr = global.alloc(layout);
+                let result = allocator.allocate(layout);
+                assert_eq!(ptr.is_null(), result.is_err(), "{:?}", layout);
+                if let Ok(slice) = result {
+                    assert_eq!(slice.len(), layout.size());
+                }
+            }
+        }
+    }
+
+    #[test]
+    /// Check a heapblock wrapping around the address space is rejected.
+    fn deblockator_overflowing_block() {
+        struct WrappingAlloc(Cell<usize>);
+
+        unsafe impl Allocator for WrappingAlloc {
+            fn allocate(&self, _layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                let ptr = NonNull::new((usize::MAX - 4095) as *mut u8).ok_or(AllocError)?;
+                Ok(NonNull::slice_from_raw_parts(ptr, 4096))
+            }
+
+            unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        impl BlockAllocator for WrappingAlloc {}
+
+        let va: Deblockator<WrappingAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(WrappingAlloc(Cell::new(0)));
+        unsafe {
+            let layout = Layout::from_size_align(16, 8).expect("bad layout");
+            assert!(va.alloc(layout).is_null());
+            assert!((*va.first_block.get()).is_none());
+            assert_eq!((*va.block_allocator.get()).0.get(), 1);
+        }
+    }
+
+    #[test]
+    /// Check a heapblock overlapping the heap is rejected.
+    fn deblockator_overlapping_block() {
+        struct OverlappingAlloc {
+            pages: Box<UnsafeCell<[Page; 2]>>,
+            calls: Cell<usize>,
+            freed: Cell<usize>,
+        }
+
+        unsafe impl Allocator for OverlappingAlloc {
+            fn allocate(&self, _layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                // the second block starts in the middle of the first one
+                let offset = self.calls.get() * 2048;
+                self.calls.set(self.calls.get() + 1);
+                let base = self.pages.get() as *mut u8;
+                let ptr = NonNull::new(unsafe { base.add(offset) }).ok_or(AllocError)?;
+                Ok(NonNull::slice_from_raw_parts(ptr, 4096))
+            }
+
+            unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+                self.freed.set(self.freed.get() + 1);
+            }
+        }
+
+        impl BlockAllocator for OverlappingAlloc {}
+
+        let va: Deblockator<OverlappingAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(OverlappingAlloc {
+                pages: Box::new(UnsafeCell::new([Page([0; 4096]), Page([0; 4096])])),
+                calls: Cell::new(0),
+                freed: Cell::new(0),
+            });
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            assert!(!a.is_null() && !b.is_null());
+            b.write_bytes(0x42, 1500);
+
+            // the overlapping block is given back without touching the heap
+            assert!(va.alloc(layout).is_null());
+            let backend = &*va.block_allocator.get();
+            assert_eq!(backend.freed.get(), 1);
+            assert!((*va.first_block.get()).as_ref().unwrap().next.is_none());
+            assert_eq!(b.add(1499).read(), 0x42);
+            va.dealloc(a, layout);
+            va.dealloc(b, layout);
+        }
+    }
+
+    #[test]
+    /// Check pointers are classified according to the memory they point to.
+    fn deblockator_verify_pointer() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            let small = Layout::from_size_align(64, 8).expect("bad layout");
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let a = va.alloc(small);
+            let b = va.alloc(small);
+            let c = va.alloc(large);
+            va.dealloc(a, small);
+
+            assert_eq!(va.verify_pointer(b), PointerKind::Small);
+            assert_eq!(va.verify_pointer(b.add(63)), PointerKind::Small);
+            assert_eq!(va.verify_pointer(a), PointerKind::Hole);
+            assert_eq!(va.verify_pointer(mock.block(0)), PointerKind::Header);
+            assert_eq!(va.verify_pointer(c), PointerKind::Large);
+            assert_eq!(va.verify_pointer(mock.block(2)), PointerKind::Foreign);
+            let local = 0u8;
+            assert_eq!(va.verify_pointer(&local), PointerKind::Foreign);
+            assert_eq!(va.verify_pointer(core::ptr::null()), PointerKind::Foreign);
+
+            va.dealloc(b, small);
+            va.dealloc(c, large);
+            assert_eq!(va.verify_pointer(c), PointerKind::Foreign);
+        }
+    }
+
+    #[test]
+    /// Check small allocations fill the scratch buffer before spilling to new heapblocks.
+    fn deblockator_seed_scratch() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).retain_first_block(false);
+        let mock = unsafe { &*va.block_allocator.get() };
+        let scratch: &'static mut [u8] = Box::leak(vec![0u8; 1024].into_boxed_slice());
+        let range = scratch.as_ptr() as usize..scratch.as_ptr() as usize + 1024;
+        assert!(!va.seed_scratch(Box::leak(vec![0u8; 16].into_boxed_slice())));
+        assert!(va.seed_scratch(scratch));
+        assert!(!va.seed_scratch(Box::leak(vec![0u8; 1024].into_boxed_slice())));
+
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).expect("bad layout");
+            let mut ptrs = Vec::new();
+            loop {
+                let ptr = va.alloc(layout);
+                ptrs.push(ptr);
+                if !range.contains(&(ptr as usize)) {
+                    break;
+                }
+            }
+
+            // the scratch buffer is full, so the last allocation spilled
+            assert!(ptrs.len() > 5);
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+            let spilled = ptrs.pop().unwrap();
+            assert!((mock.block(0) as usize..mock.block(1) as usize).contains(&(spilled as usize)));
+
+            // the scratch heapblock is kept once empty, unlike the other one
+            va.dealloc(spilled, layout);
+            for ptr in ptrs {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(mock.allocated.get(), [false, false, false]);
+            assert_eq!(va.trim(0), 0);
+            let first = (*va.first_block.get()).as_ref().unwrap();
+            assert!(range.contains(&(*first as *const HeapBlock as usize)));
+        }
+    }
+
+    #[test]
+    /// Check all heapblocks are given back before the underlying allocator is dropped.
+    fn deblockator_drop() {
+        /// A mock allocator checking all its blocks were deallocated.
+        struct StrictAlloc(MockAlloc);
+
+        unsafe impl Allocator for StrictAlloc {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for StrictAlloc {}
+
+        impl Drop for StrictAlloc {
+            fn drop(&mut self) {
+                assert_eq!(self.0.allocated.get(), [false; 3], "blocks leaked");
+            }
+        }
+
+        let va: Deblockator<StrictAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(StrictAlloc(MockAlloc::new()));
+        let scratch: &'static mut [u8] = Box::leak(vec![0u8; 256].into_boxed_slice());
+        assert!(va.seed_scratch(scratch));
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            for _ in 0..4 {
+                assert!(!va.alloc(layout).is_null());
+            }
+            // a large allocation is left outstanding as well
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            assert!(!va.alloc(large).is_null());
+            assert_eq!((*va.block_allocator.get()).0.allocated.get(), [true; 3]);
+        }
+        drop(va);
+    }
+
+    #[test]
+    /// Check a coarser size granularity lets holes be reused by similar sizes.
+    fn deblockator_size_granularity() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).size_granularity(32);
+
+        unsafe {
+            let layout_17 = Layout::from_size_align(17, 8).expect("bad layout");
+            let layout_20 = Layout::from_size_align(20, 8).expect("bad layout");
+            assert_eq!(va.block_layout(va.tagged(layout_17)).unwrap().size(), 32);
+            assert_eq!(va.block_layout(va.tagged(layout_20)).unwrap().size(), 32);
+
+            let a = va.alloc(layout_17);
+            let b = va.alloc(layout_17);
+            assert_eq!(b as usize - a as usize, 32);
+            va.dealloc(a, layout_17);
+            assert_eq!(va.alloc(layout_20), a);
+
+            va.dealloc(a, layout_20);
+            va.dealloc(b, layout_17);
+        }
+    }
+
+    #[test]
+    /// Check next-fit starts from the rover, and can be reset to the front.
+    fn deblockator_next_fit() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).next_fit(true);
+        let mock = unsafe { &*va.block_allocator.get() };
+        assert_eq!(va.rover_position(), None);
+
+        unsafe {
+            // fill the first heapblock and spill to a second one
+            let layout = Layout::from_size_align(1000, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..5).map(|_| va.alloc(layout)).collect();
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+            assert_eq!(va.rover_position(), Some(mock.block(1) as usize));
+
+            // the hole left in the first heapblock is skipped
+            va.dealloc(ptrs[0], layout);
+            let ptr = va.alloc(layout);
+            assert!(ptr as usize > mock.block(1) as usize);
+            va.dealloc(ptr, layout);
+
+            // until the rover is reset
+            va.reset_rover();
+            assert_eq!(va.rover_position(), Some(mock.block(0) as usize));
+            assert_eq!(va.alloc(layout), ptrs[0]);
+        }
+    }
+
+    #[test]
+    /// Check a fragmented heap is compacted instead of growing.
+    fn deblockator_compact_threshold() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).compact_threshold(0.5);
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            // leave holes at the end of the first heapblock and the start of the second
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..4).map(|_| va.alloc(layout)).collect();
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+            va.dealloc(ptrs[2], layout);
+
+            // the holes only fit the allocation once merged
+            let large = Layout::from_size_align(2000, 8).expect("bad layout");
+            let ptr = va.alloc(large);
+            assert!(ptr as usize > mock.block(0) as usize);
+            assert!((ptr as usize) < mock.block(1) as usize);
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+            va.dealloc(ptr, large);
+        }
+
+        // without compaction, the heap grows
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..4).map(|_| va.alloc(layout)).collect();
+            va.dealloc(ptrs[2], layout);
+            va.alloc(Layout::from_size_align(2000, 8).expect("bad layout"));
+            assert_eq!(mock.allocated.get(), [true, true, true]);
+        }
+    }
+
+    #[test]
+    /// Check holes are counted by power of two over the whole heap.
+    fn deblockator_histogram_of_holes() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mut buckets = [0; 16];
+        va.histogram_of_holes(&mut buckets);
+        assert_eq!(buckets, [0; 16]);
+
+        unsafe {
+            let sizes = [64, 16, 256, 16];
+            let layouts: Vec<_> = sizes
+                .iter()
+                .map(|&size| Layout::from_size_align(size, 8).expect("bad layout"))
+                .collect();
+            let ptrs: Vec<_> = layouts.iter().map(|&layout| va.alloc(layout)).collect();
+            va.dealloc(ptrs[0], layouts[0]);
+            va.dealloc(ptrs[2], layouts[2]);
+
+            // holes of 64 and 256 bytes, and the end of the heapblock
+            va.histogram_of_holes(&mut buckets);
+            let mut expected = [0; 16];
+            expected[6] = 1;
+            expected[8] = 1;
+            expected[11] = 1;
+            assert_eq!(buckets, expected);
+
+            // larger holes are counted in the last bucket
+            let mut buckets = [0; 8];
+            va.histogram_of_holes(&mut buckets);
+            assert_eq!(buckets, [0, 0, 0, 0, 0, 0, 1, 2]);
+        }
+    }
+
+    #[test]
+    /// Check bounded allocations give up on long scans instead of growing.
+    fn deblockator_alloc_bounded() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            // leave many holes too small for a larger allocation
+            let small = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..32).map(|_| va.alloc(small)).collect();
+            for ptr in ptrs.iter().step_by(2) {
+                va.dealloc(*ptr, small);
+            }
+
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            assert_eq!(va.alloc_bounded(layout, 8), None);
+            let ptr = va.alloc_bounded(layout, 64).expect("bounded alloc");
+            assert!(ptr as usize >= ptrs[31] as usize);
+            va.dealloc(ptr, layout);
+
+            // holes that fit are found within the budget
+            assert_eq!(va.alloc_bounded(small, 1), Some(ptrs[0]));
+
+            // the heap never grows
+            let large = Layout::from_size_align(2000, 8).expect("bad layout");
+            assert!(va.alloc_bounded(large, usize::MAX).is_some());
+            assert_eq!(va.alloc_bounded(large, usize::MAX), None);
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+            assert!(!va.alloc(large).is_null());
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "counters")]
+    /// Check allocations are counted according to their path.
+    fn deblockator_path_counters() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let small = Layout::from_size_align(64, 8).expect("bad layout");
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let a = va.alloc(small);
+            let b = va.alloc(large);
+            let c = va.alloc(small);
+            va.dealloc(a, small);
+            va.dealloc(b, large);
+
+            assert_eq!(va.small_alloc_count(), 2);
+            assert_eq!(va.large_alloc_count(), 1);
+            assert_eq!(va.small_dealloc_count(), 1);
+            assert_eq!(va.large_dealloc_count(), 1);
+
+            va.dealloc(c, small);
+            assert_eq!(va.small_dealloc_count(), 2);
+        }
+    }
+
+    #[test]
+    /// Check a frozen heap only serves allocations from its free space.
+    fn deblockator_freeze() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            va.freeze();
+
+            // the first heapblock still has room for one more allocation
+            let b = va.alloc(layout);
+            assert!(!b.is_null());
+            assert!(va.alloc(layout).is_null());
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            assert!(va.alloc(large).is_null());
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+
+            va.unfreeze();
+            let c = va.alloc(layout);
+            assert!(!c.is_null());
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+            va.dealloc(a, layout);
+            va.dealloc(b, layout);
+            va.dealloc(c, layout);
+        }
+    }
+
+    #[test]
+    /// Check reallocations resize allocations in place when possible.
+    fn deblockator_realloc_in_place() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            // grow into the free end of the heapblock
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            a.write_bytes(0x42, 64);
+            let grown = va.realloc(a, layout, 256);
+            assert_eq!(grown, a);
+            assert_eq!(a.add(63).read(), 0x42);
+
+            // the memory after the grown allocation is still free
+            let b = va.alloc(layout);
+            assert!(b as usize >= a as usize + 256);
+
+            // shrink, and reuse the freed tail
+            let layout = Layout::from_size_align(256, 8).expect("bad layout");
+            let shrunk = va.realloc(a, layout, 64);
+            assert_eq!(shrunk, a);
+            let c = va.alloc(Layout::from_size_align(128, 8).expect("bad layout"));
+            assert!(c as usize > a as usize && (c as usize) < b as usize);
+
+            // move when the next allocation is in the way
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let moved = va.realloc(a, layout, 512);
+            assert_ne!(moved, a);
+            assert_eq!(moved.add(63).read(), 0x42);
+            assert!(moved as usize > b as usize);
+        }
+    }
+
+    #[test]
+    /// Check zeroed allocations only clear memory that may have been written to.
+    fn deblockator_alloc_zeroed() {
+        /// A mock allocator zeroing its blocks when they are deallocated.
+        struct ZeroedAlloc(MockAlloc);
+
+        unsafe impl Allocator for ZeroedAlloc {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                ptr.as_ptr().write_bytes(0, 4096);
+                self.0.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for ZeroedAlloc {
+            fn zeroes_blocks(&self) -> bool {
+                true
+            }
+        }
+
+        let va: Deblockator<ZeroedAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(ZeroedAlloc(MockAlloc::new()));
+
+        unsafe {
+            let small = Layout::from_size_align(16, 8).expect("bad layout");
+            let first = va.alloc(small);
+
+            // the free memory of a pristine heapblock is trusted to be zeroed
+            first.add(300).write(0x42);
+            let layout = Layout::from_size_align(512, 8).expect("bad layout");
+            let a = va.alloc_zeroed(layout);
+            assert!(first.add(300) > a && first.add(300) < a.add(512));
+            assert!((0..size_of::<Hole>()).all(|i| a.add(i).read() == 0));
+            let expected = if cfg!(feature = "poison") { 0 } else { 0x42 };
+            assert_eq!(first.add(300).read(), expected);
+
+            // recycled memory is always cleared
+            a.write_bytes(0xFF, 512);
+            va.dealloc(a, layout);
+            let b = va.alloc_zeroed(layout);
+            assert_eq!(b, a);
+            assert!((0..512).all(|i| b.add(i).read() == 0));
+
+            // as is large memory, unless the underlying allocator zeroes it
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let c = va.alloc_zeroed(large);
+            assert!((0..3000).all(|i| c.add(i).read() == 0));
+            va.dealloc(c, large);
+            va.dealloc(b, layout);
+            va.dealloc(first, small);
+        }
+    }
+
+    #[test]
+    /// Check the heap usage snapshot follows allocations and deallocations.
+    fn deblockator_stats() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        assert_eq!(va.stats(), Stats::default());
+
+        unsafe {
+            let small = Layout::from_size_align(64, 8).expect("bad layout");
+            let size = va.block_layout(va.tagged(small)).unwrap().size();
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let a = va.alloc(small);
+            let b = va.alloc(small);
+            let c = va.alloc(large);
+
+            let header = size_of::<HeapBlock>();
+            let stats = va.stats();
+            assert_eq!(stats.heapblock_count, 1);
+            assert_eq!(stats.total_bytes, 4096);
+            assert_eq!(stats.used_bytes, header + 2 * size);
+            assert_eq!(stats.free_bytes, 4096 - header - 2 * size);
+            assert_eq!(stats.largest_free_block, stats.free_bytes);
+            assert_eq!(stats.large_allocation_count, 1);
+
+            va.dealloc(a, small);
+            va.dealloc(c, large);
+            let stats = va.stats();
+            assert_eq!(stats.used_bytes, header + size);
+            assert_eq!(stats.large_allocation_count, 0);
+            va.dealloc(b, small);
+            assert_eq!(va.stats().used_bytes, header);
+        }
+    }
+
+    #[test]
+    /// Check the fragmentation rises as the free memory is split in holes.
+    fn deblockator_stats_fragmentation() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        assert_eq!(va.stats().fragmentation(), 0.0);
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..32).map(|_| va.alloc(layout)).collect();
+            let stats = va.stats();
+            assert_eq!(stats.largest_free_block, stats.free_bytes);
+            assert_eq!(stats.fragmentation(), 0.0);
+
+            // free every other allocation, leaving small holes behind
+            let mut last = 0.0;
+            for &ptr in ptrs.iter().step_by(2) {
+                va.dealloc(ptr, layout);
+                let fragmentation = va.stats().fragmentation();
+                assert!(fragmentation >= last);
+                last = fragmentation;
+            }
+            let stats = va.stats();
+            assert!(stats.largest_free_block < stats.free_bytes);
+            assert!(stats.fragmentation() > 0.1);
+
+            // the holes coalesce again once everything is freed
+            for &ptr in ptrs.iter().skip(1).step_by(2) {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(va.stats().fragmentation(), 0.0);
+        }
+    }
+
+    #[test]
+    /// Check the fit strategy chooses the hole allocations are carved from.
+    fn deblockator_best_fit() {
+        /// Free the first and third of four allocations, then allocate again.
+        fn fragment<F: FitStrategy>(
+            va: &Deblockator<MockAlloc, U4096, U4096, U2048, U4096, F>,
+        ) -> [*mut u8; 6] {
+            let (small, medium, large) = (
+                Layout::from_size_align(32, 8).expect("bad layout"),
+                Layout::from_size_align(64, 8).expect("bad layout"),
+                Layout::from_size_align(256, 8).expect("bad layout"),
+            );
+            unsafe {
+                let a = va.alloc(large);
+                let b = va.alloc(small);
+                let c = va.alloc(medium);
+                let d = va.alloc(small);
+                va.dealloc(a, large);
+                va.dealloc(c, medium);
+                [a, b, c, d, va.alloc(medium), va.alloc(large)]
+            }
+        }
+
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096, FirstFit> =
+            Deblockator::new(MockAlloc::new());
+        let [a, _, _, d, medium, large] = fragment(&va);
+        assert_eq!(medium, a);
+        assert!(large > d);
+        let first_fit = va.most_fragmented_block().expect("no heapblock").1;
+
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096, BestFit> =
+            Deblockator::new(MockAlloc::new());
+        let [a, _, c, _, medium, large] = fragment(&va);
+        assert_eq!(medium, c);
+        assert_eq!(large, a);
+        let best_fit = va.most_fragmented_block().expect("no heapblock").1;
+        assert!(best_fit < first_fit);
+    }
+
+    #[test]
+    /// Check failed allocations report why they failed.
+    fn deblockator_try_alloc() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let layout = Layout::from_size_align(3000, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..3).map(|_| va.try_alloc(layout)).collect();
+            assert!(ptrs.iter().all(Result::is_ok));
+            assert_eq!(va.try_alloc(layout), Err(AllocFailure::Exhausted));
+
+            va.dealloc(ptrs[2].unwrap().as_ptr(), layout);
+            va.freeze();
+            assert_eq!(va.try_alloc(layout), Err(AllocFailure::NoFit));
+            va.unfreeze();
+            assert!(va.try_alloc(layout).is_ok());
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _lock = va.mutex.lock().expect("could not lock");
+                panic!("panic while allocating");
+            }));
+            assert!(result.is_err());
+            assert_eq!(va.try_alloc(layout), Err(AllocFailure::Poisoned));
+        }
+    }
+
+    #[test]
+    /// Check layouts that can never be allocated are told apart from an exhausted heap.
+    fn deblockator_try_alloc_too_large() {
+        unsafe {
+            // the padded size overflows
+            let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+                Deblockator::new(MockAlloc::new());
+            let huge = Layout::from_size_align(isize::MAX as usize - 4000, 8).expect("bad layout");
+            assert_eq!(va.try_alloc(huge), Err(AllocFailure::TooLarge));
+
+            // large allocations are disabled
+            let va: Deblockator<MockAlloc, U4096, U4096, U0, U4096> =
+                Deblockator::new(MockAlloc::new());
+            let layout = Layout::from_size_align(5000, 8).expect("bad layout");
+            assert_eq!(va.try_alloc(layout), Err(AllocFailure::TooLarge));
+            assert_eq!(va.try_alloc(huge), Err(AllocFailure::TooLarge));
+            assert_eq!((*va.block_allocator.get()).allocated.get(), [false; 3]);
+        }
+    }
+
+    #[test]
+    /// Check all empty heapblocks are freed at once, including the first one.
+    fn deblockator_reclaim() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::with_capacity(MockAlloc::new(), 10000).expect("out of memory");
+        let mock = unsafe { &*va.block_allocator.get() };
+        assert_eq!(mock.allocated.get(), [true, true, true]);
+
+        unsafe {
+            // empty the first heapblock, and keep the second one occupied
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..3).map(|_| va.alloc(layout)).collect();
+            va.dealloc(ptrs[0], layout);
+            va.dealloc(ptrs[1], layout);
+            assert_eq!(mock.allocated.get(), [true, true, true]);
+
+            assert_eq!(va.reclaim(), (2, 8192));
+            assert_eq!(mock.allocated.get(), [false, true, false]);
+            assert_eq!(va.reclaim(), (0, 0));
+            let first = (*va.first_block.get()).as_ref().expect("no heapblock");
+            assert!(first.contains(ptrs[2]));
+            va.dealloc(ptrs[2], layout);
+        }
+    }
+
+    #[test]
+    /// Check concurrent allocations and deallocations keep the heap consistent.
+    fn deblockator_threads() {
+        /// Allocate heapblocks with the system allocator.
+        struct SystemBlocks;
+
+        unsafe impl Allocator for SystemBlocks {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                std::alloc::System.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                std::alloc::System.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for SystemBlocks {}
+
+        let va: Deblockator<SystemBlocks, U4096, U4096, U2048, U4096> =
+            Deblockator::new(SystemBlocks);
+        std::thread::scope(|scope| {
+            for thread in 0..4u8 {
+                let va = &va;
+                scope.spawn(move || unsafe {
+                    let mut live = Vec::new();
+                    for i in 0..1000usize {
+                        // mix small and large allocations, and free some as we go
+                        let size = 16 + (i * 37 + thread as usize * 101) % 3000;
+                        let layout = Layout::from_size_align(size, 8).expect("bad layout");
+                        let ptr = va.alloc(layout);
+                        assert!(!ptr.is_null());
+                        ptr.write_bytes(thread, size);
+                        live.push((ptr, layout));
+                        if i % 3 == 0 {
+                            let (ptr, layout) = live.swap_remove(i % live.len());
+                            assert_eq!(ptr.add(layout.size() - 1).read(), thread);
+                            va.dealloc(ptr, layout);
+                        }
+                    }
+                    for (ptr, layout) in live {
+                        assert!((0..layout.size()).all(|i| ptr.add(i).read() == thread));
+                        va.dealloc(ptr, layout);
+                    }
+                });
+            }
+        });
+        assert!(!va.is_poisoned());
+        assert_eq!(va.stats().used_bytes, size_of::<HeapBlock>());
+    }
+
+    #[test]
+    /// Check an allocation of exactly the large size is freed as a large one.
+    fn deblockator_large_threshold() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            let layout = Layout::from_size_align(2048, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert_eq!(ptr, mock.block(0));
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+            va.dealloc(ptr, layout);
+            assert_eq!(mock.allocated.get(), [false, false, false]);
+        }
+    }
+
+    #[test]
+    /// Check allocations honour alignments larger than the hole alignment.
+    fn deblockator_large_alignment() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+
+        unsafe {
+            let small = Layout::from_size_align(16, 8).expect("bad layout");
+            let first = va.alloc(small);
+            let layout = Layout::from_size_align(48, 64).expect("bad layout");
+            let ptr = va.alloc(layout);
+            assert!((ptr as usize).is_multiple_of(64));
+
+            // the leading padding is left as a hole
+            assert!(ptr as usize - first as usize > 2 * small.size());
+            assert_eq!(va.verify_pointer(ptr.sub(16)), PointerKind::Hole);
+            let second = va.alloc(small);
+            assert!(second > first && second < ptr);
+
+            va.dealloc(ptr, layout);
+            va.dealloc(first, small);
+            va.dealloc(second, small);
+        }
+    }
+
+    #[test]
+    /// Check the allocator can be synchronised by a custom mutex.
+    fn deblockator_custom_mutex() {
+        /// A mutex that does nothing, only usable from a single thread.
+        struct NoopMutex(PhantomData<*mut ()>);
+
+        unsafe impl RawMutex for NoopMutex {
+            const INIT: Self = NoopMutex(PhantomData);
+            fn lock(&self) {}
+            unsafe fn unlock(&self) {}
+        }
+
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096, FirstFit, NoopMutex> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let layout = Layout::from_size_align_unchecked(64, 8);
+            let p1 = va.alloc(layout);
+            let p2 = va.alloc(layout);
+            assert!(!p1.is_null() && !p2.is_null());
+            assert_ne!(p1, p2);
+            va.dealloc(p1, layout);
+            va.dealloc(p2, layout);
+            assert_eq!(
+                (*va.block_allocator.get()).allocated.get(),
+                [true, false, false]
+            );
+        }
+        assert!(!va.is_poisoned());
+    }
+
+    #[test]
+    /// Check the block allocator is only recovered once nothing is allocated.
+    fn deblockator_into_inner() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let layout = Layout::from_size_align_unchecked(64, 8);
+            let ptr = va.alloc(layout);
+            assert!(!ptr.is_null());
+
+            let va = va.into_inner().err().expect("recovered a used allocator");
+            assert_eq!(
+                (*va.block_allocator.get()).allocated.get(),
+                [true, false, false]
+            );
+
+            va.dealloc(ptr, layout);
+            let ma = va.into_inner().expect("could not recover allocator");
+            assert_eq!(ma.allocated.get(), [false; 3]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "counters")]
+    /// Check the peak usage is the maximum of the allocated bytes.
+    fn deblockator_peak_usage() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let small = Layout::from_size_align(100, 8).expect("bad layout");
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let a = va.alloc(small);
+            let b = va.alloc(large);
+            assert!(!a.is_null() && !b.is_null());
+            assert_eq!(va.peak_usage(), 3100);
+
+            va.dealloc(b, large);
+            let c = va.alloc(small);
+            assert_eq!(va.peak_usage(), 3100);
+
+            va.reset_peak();
+            assert_eq!(va.peak_usage(), 200);
+            va.dealloc(a, small);
+            va.dealloc(c, small);
+            assert_eq!(va.peak_usage(), 200);
+        }
+    }
+
+    #[test]
+    /// Check pointers are recognized within heapblocks and large allocations.
+    fn deblockator_contains() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let small = Layout::from_size_align(64, 8).expect("bad layout");
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let a = va.alloc(small);
+            let b = va.alloc(large);
+            assert!(va.contains(a));
+            assert!(va.contains(a.add(100)));
+            assert!(va.contains(b.add(2999)));
+
+            let outside = 0u8;
+            assert!(!va.contains(&outside));
+            let unused = (*va.block_allocator.get()).block(2);
+            assert!(!va.contains(unused));
+
+            va.dealloc(b, large);
+            assert!(!va.contains(b));
+            va.dealloc(a, small);
+        }
+    }
+
+    #[test]
+    /// Check live large allocations are recognized without the help of the underlying allocator.
+    fn deblockator_contains_without_owns() {
+        /// Allocate blocks with the system allocator, without implementing `owns`.
+        struct SystemBlocks;
+
+        unsafe impl Allocator for SystemBlocks {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                std::alloc::System.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                std::alloc::System.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for SystemBlocks {}
+
+        let va: Deblockator<SystemBlocks, U4096, U4096, U2048, U4096> =
+            Deblockator::new(SystemBlocks);
+        unsafe {
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let ptr = va.alloc(large);
+            assert!(!ptr.is_null());
+            assert_eq!(va.verify_pointer(ptr), PointerKind::Large);
+            assert_eq!(va.verify_pointer(ptr.add(2999)), PointerKind::Large);
+            assert!(va.contains(ptr));
+            assert!(va.contains(ptr.add(2999)));
+
+            let outside = 0u8;
+            assert_eq!(va.verify_pointer(&outside), PointerKind::Unknown);
+            va.dealloc(ptr, large);
+            assert_eq!(va.verify_pointer(ptr), PointerKind::Unknown);
+            assert!(!va.contains(ptr));
+        }
+    }
+
+    #[test]
+    /// Check allocating in the current heapblocks never grows the heap.
+    fn deblockator_alloc_in_existing_blocks() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            let layout = va
+                .block_layout(Layout::from_size_align_unchecked(64, 8))
+                .unwrap();
+            let mut scanned = 0;
+            assert_eq!(
+                va.alloc_in_existing_blocks_locked(layout, &mut scanned),
+                None
+            );
+            assert_eq!(allocated(), [false; 3]);
+
+            let a = va
+                .alloc_new_block_locked(layout, &mut scanned)
+                .unwrap()
+                .as_ptr();
+            let b = va
+                .alloc_in_existing_blocks_locked(layout, &mut scanned)
+                .expect("could not allocate");
+            assert_eq!(b, a.add(layout.size()));
+            assert_eq!(allocated(), [true, false, false]);
+        }
+    }
+
+    #[test]
+    /// Check allocating in a new heapblock links it last, even if others have room.
+    fn deblockator_alloc_new_block() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            let layout = va
+                .block_layout(Layout::from_size_align_unchecked(64, 8))
+                .unwrap();
+            let a = va.alloc_new_block_locked(layout, &mut 0).unwrap().as_ptr();
+            let b = va.alloc_new_block_locked(layout, &mut 0).unwrap().as_ptr();
+            assert_eq!(allocated(), [true, true, false]);
+            let base = (*va.block_allocator.get()).block(0) as usize;
+            assert_eq!(va.block_id(a), Some(BlockId(base)));
+
+            let first = (*va.first_block.get()).as_ref().expect("no heapblock");
+            let second = first.next.as_ref().expect("no second heapblock");
+            assert!(first.contains(a) && second.contains(b));
+            assert!(second.next.is_none());
+        }
+    }
+
+    #[test]
+    /// Check large allocations bypass the heapblocks.
+    fn deblockator_alloc_large() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let layout = Layout::from_size_align_unchecked(64, 8);
+            let ptr = va.alloc_large_locked(layout).unwrap().as_ptr();
+            assert_eq!(ptr, (*va.block_allocator.get()).block(0));
+            assert_eq!(*va.large_count.get(), 1);
+            assert!((*va.large_registry.get()).contains(ptr as usize));
+            assert!((*va.first_block.get()).is_none());
+            va.dealloc_large_locked(ptr);
+            assert_eq!((*va.block_allocator.get()).allocated.get(), [false; 3]);
+            assert!(!(*va.large_registry.get()).contains(ptr as usize));
+        }
+    }
+
+    #[test]
+    /// Check large allocations are given back with the layout they were made with.
+    fn deblockator_dealloc_large_recorded_layout() {
+        /// A mock allocator recording the layout of the last deallocation.
+        struct RecordingAlloc(MockAlloc, Cell<Option<Layout>>);
+
+        unsafe impl Allocator for RecordingAlloc {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.1.set(Some(layout));
+                self.0.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for RecordingAlloc {}
+
+        let va: Deblockator<RecordingAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(RecordingAlloc(MockAlloc::new(), Cell::new(None)));
+        let recorded = || unsafe { (*va.block_allocator.get()).1.take() };
+        let padded = Layout::from_size_align(4096, 4096).expect("bad layout");
+
+        unsafe {
+            let layout = Layout::from_size_align(3000, 4096).expect("bad layout");
+            let ptr = va.alloc_large_locked(layout).unwrap().as_ptr();
+            va.dealloc_untagged_locked(ptr, layout);
+            assert_eq!(recorded(), Some(padded));
+
+            // a layout differing from the allocation one is not used
+            let ptr = va.alloc_large_locked(layout).unwrap().as_ptr();
+            va.dealloc_untagged_locked(ptr, Layout::from_size_align_unchecked(3000, 8));
+            assert_eq!(recorded(), Some(padded));
+        }
+    }
+
+    #[test]
+    /// Check reserved heapblocks serve allocations without growing the heap.
+    fn deblockator_reserve_blocks() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            assert_eq!(va.reserve_blocks(2), Ok(()));
+            assert_eq!(allocated(), [true, true, false]);
+
+            let layout = Layout::from_size_align(1000, 8).expect("bad layout");
+            let ptrs = [(); 6].map(|_| va.alloc(layout));
+            assert!(ptrs.iter().all(|p| !p.is_null()));
+            assert_eq!(allocated(), [true, true, false]);
+
+            // the blocks obtained before a failure are kept
+            assert_eq!(va.reserve_blocks(2), Err(ReserveError { created: 1 }));
+            assert_eq!(allocated(), [true; 3]);
+            assert_eq!(va.stats().heapblock_count, 3);
+
+            for ptr in ptrs {
+                va.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    /// Check the allocator can back collections through the `Allocator` trait.
+    fn deblockator_allocator_vec() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        {
+            let mut v = Vec::new_in(&va);
+            for i in 0..256u32 {
+                v.push(i);
+            }
+            assert!(v.iter().copied().eq(0..256));
+            assert!(va.contains(v.as_ptr() as *const u8));
+
+            let b = Box::new_in(0xDEADu32, &va);
+            assert_eq!(*b, 0xDEAD);
+        }
+        let first = unsafe { (*va.first_block.get()).as_ref().expect("no heapblock") };
+        assert!(first.is_empty());
+    }
+
+    #[test]
+    /// Check a large size of zero allocates everything in heapblocks.
+    fn deblockator_no_large_fastpath() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U0, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let allocated = || (*va.block_allocator.get()).allocated.get();
+            let capacity = HeapBlock::<U4096>::capacity();
+            let size = capacity - cfg!(feature = "tags") as usize;
+            let full = Layout::from_size_align(size, 8).expect("bad layout");
+            assert_eq!(va.block_layout(va.tagged(full)).unwrap().size(), capacity);
+
+            let ptr = va.alloc(full);
+            assert!(!ptr.is_null());
+            assert_eq!(va.stats().heapblock_count, 1);
+            assert_eq!(*va.large_count.get(), 0);
+
+            // a layout not fitting in a heapblock fails without leaking a block
+            let over = Layout::from_size_align(capacity + 8, 8).expect("bad layout");
+            assert!(va.alloc(over).is_null());
+            assert_eq!(allocated(), [true, false, false]);
+
+            va.dealloc(ptr, full);
+        }
+    }
+
+    #[test]
+    /// Check the heapblock snapshots follow the allocations.
+    fn deblockator_snapshot_blocks() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mut infos = [BlockInfo::default(); 2];
+        assert_eq!(va.snapshot_blocks(&mut infos), 0);
+        unsafe {
+            let layout = Layout::from_size_align(256, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            assert_eq!(va.snapshot_blocks(&mut infos), 1);
+            let before = infos[0];
+            assert_eq!(before.base, (*va.block_allocator.get()).block(0) as usize);
+            assert_eq!(before.size, 4096);
+            assert_eq!(before.holes, 1);
+
+            let b = va.alloc(layout);
+            assert_eq!(va.snapshot_blocks(&mut infos), 1);
+            assert!(infos[0].largest_hole < before.largest_hole);
+            assert!(infos[0].free_bytes < before.free_bytes);
+
+            // only the first heapblocks are described in a short buffer
+            let large = Layout::from_size_align(2000, 8).expect("bad layout");
+            let c = va.alloc(large);
+            let d = va.alloc(large);
+            assert_eq!(va.snapshot_blocks(&mut infos), 2);
+            assert!(infos[1].base != before.base);
+            assert_eq!(va.snapshot_blocks(&mut infos[..1]), 1);
+
+            for (ptr, layout) in [(a, layout), (b, layout), (c, large), (d, large)] {
+                va.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    /// Check over-aligned small allocations are carved from heapblocks.
+    fn deblockator_overaligned_buffers() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let layouts = [128, 256, 512].map(|align| Layout::from_size_align(32, align).unwrap());
+            let ptrs = layouts.map(|layout| va.alloc(layout));
+            for (ptr, layout) in ptrs.iter().zip(&layouts) {
+                assert!((*ptr as usize).is_multiple_of(layout.align()));
+                assert!(va.block_id(*ptr).is_some());
+            }
+            assert_eq!(*va.large_count.get(), 0);
+            assert_eq!(va.stats().heapblock_count, 1);
+
+            // the padding around the buffers was given back as holes
+            for (ptr, layout) in ptrs.iter().zip(&layouts) {
+                va.dealloc(*ptr, *layout);
+            }
+            let first = (*va.first_block.get()).as_ref().expect("no heapblock");
+            assert!(first.is_empty());
+        }
+    }
+
+    #[test]
+    /// Check a heap confined to a region fails once the region is full.
+    fn deblockator_new_in_region() {
+        #[repr(C, align(4096))]
+        struct Region([u8; 4096]);
+
+        let region = || &mut Box::leak(Box::new(Region([0; 4096]))).0;
+        assert!(Deblockator::<NoBlocks>::new_in_region(&mut region()[1..]).is_err());
+        assert!(Deblockator::<NoBlocks>::new_in_region(&mut region()[..32]).is_err());
+
+        let va: Deblockator<NoBlocks, U4096, U4096, U2048, U4096> =
+            Deblockator::new_in_region(region()).expect("could not use region");
+        unsafe {
+            let layout = Layout::from_size_align(256, 8).expect("bad layout");
+            let mut ptrs = Vec::new();
+            loop {
+                let ptr = va.alloc(layout);
+                if ptr.is_null() {
+                    break;
+                }
+                ptrs.push(ptr);
+            }
+            let size = va.block_layout(va.tagged(layout)).unwrap().size();
+            assert_eq!(ptrs.len(), HeapBlock::<U4096>::capacity() / size);
+            assert_eq!(va.stats().heapblock_count, 1);
+            assert!(va
+                .alloc(Layout::from_size_align(3000, 8).unwrap())
+                .is_null());
+
+            // freed memory is reused, but the region is never released
+            let last = ptrs.pop().expect("nothing allocated");
+            va.dealloc(last, layout);
+            assert_eq!(va.alloc(layout), last);
+            for ptr in ptrs.into_iter().chain([last]) {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(va.stats().heapblock_count, 1);
+        }
+    }
+
+    #[test]
+    /// Check a new heapblock fails cleanly when the underlying allocator is exhausted.
+    fn deblockator_alloc_new_block_exhausted() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let layout = va
+                .block_layout(Layout::from_size_align_unchecked(2000, 8))
+                .unwrap();
+            let ptrs = [(); 3].map(|_| va.alloc_new_block_locked(layout, &mut 0));
+            assert!(ptrs.iter().all(|ptr| ptr.is_ok()));
+            assert_eq!(
+                va.alloc_new_block_locked(layout, &mut 0),
+                Err(AllocFailure::Exhausted)
+            );
+            assert_eq!(va.stats().heapblock_count, 3);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "a fresh heapblock cannot fit")]
+    /// Check a layout not fitting a fresh heapblock is caught in debug builds.
+    fn deblockator_alloc_new_block_misfit() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            let layout = Layout::from_size_align_unchecked(4096, 8);
+            let _ = va.alloc_new_block_locked(layout, &mut 0);
+        }
+    }
+
+    #[test]
+    /// Check the allocator can be parameterized with constants.
+    fn deblockator_const_parameters() {
+        let va: ConstDeblockator<MockAlloc, 4096, 4096, 2048, 4096> =
+            Deblockator::new(MockAlloc::new());
+        let reference: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        unsafe {
+            for size in [16, 100, 1000, 3000] {
+                let layout = Layout::from_size_align(size, 8).expect("bad layout");
+                let (ptr, expected) = (va.alloc(layout), reference.alloc(layout));
+                let (block, expected_block) = (
+                    (*va.block_allocator.get()).block(0),
+                    (*reference.block_allocator.get()).block(0),
+                );
+                assert_eq!(ptr.offset_from(block), expected.offset_from(expected_block));
+            }
+            assert_eq!(va.stats(), reference.stats());
+        }
+    }
+
+    #[test]
+    /// Check large allocations are freed by address, whatever the layout given.
+    fn deblockator_dealloc_large_registered() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let allocated = || unsafe { (*va.block_allocator.get()).allocated.get() };
+        unsafe {
+            let layout = Layout::from_size_align(3000, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            assert_eq!(allocated(), [true, true, false]);
+            assert!((*va.first_block.get()).is_none());
+
+            // a different layout still exceeding the large size
+            va.dealloc(a, Layout::from_size_align(2500, 8).expect("bad layout"));
+            assert_eq!(allocated(), [false, true, false]);
+
+            // a layout that would otherwise be looked up in the heapblocks
+            va.dealloc(b, Layout::from_size_align(1000, 8).expect("bad layout"));
+            assert_eq!(allocated(), [false; 3]);
+            assert_eq!(*va.large_count.get(), 0);
+        }
+    }
+
+    #[test]
+    /// Check the largest allocation fitting a fresh heapblock spares its header.
+    fn deblockator_alloc_whole_block() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U8192, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let capacity = HeapBlock::<U4096>::capacity();
+        assert_eq!(capacity, 4096 - size_of::<HeapBlock>());
+        unsafe {
+            let size = capacity - cfg!(feature = "tags") as usize;
+            let layout = Layout::from_size_align(size, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            let base = (*va.block_allocator.get()).block(0);
+            assert_eq!(ptr, base.add(size_of::<HeapBlock>()));
+            ptr.write_bytes(0xFF, size);
+
+            let block = (*va.first_block.get()).as_ref().expect("no heapblock");
+            block.verify();
+            assert_eq!(block.size, 4096);
+            assert!(block.first.next.is_none());
+
+            // one more byte is allocated separately
+            let more = Layout::from_size_align(size + 1, 8).expect("bad layout");
+            let large = va.alloc(more);
+            assert_eq!(large, (*va.block_allocator.get()).block(1));
+            assert!(block.next.is_none());
+
+            va.dealloc(large, more);
+            va.dealloc(ptr, layout);
+            assert!(block.is_empty());
+        }
+    }
+
+    #[test]
+    /// Check heapblocks grow geometrically, and are freed with their own layout.
+    fn deblockator_growth_policy() {
+        /// Allocate heapblocks with the system allocator, checking the layouts.
+        struct SizedBlocks(RefCell<Vec<(*mut u8, Layout)>>);
+
+        unsafe impl Allocator for SizedBlocks {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                let ptr = std::alloc::System.allocate(layout)?;
+                self.0.borrow_mut().push((ptr.as_ptr() as *mut u8, layout));
+                Ok(ptr)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                let mut live = self.0.borrow_mut();
+                let i = live.iter().position(|&(p, _)| p == ptr.as_ptr());
+                let (_, allocated) = live.swap_remove(i.expect("unknown block"));
+                assert_eq!(layout, allocated);
+                std::alloc::System.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for SizedBlocks {}
+
+        let va: Deblockator<SizedBlocks, U4096, U4096, U2048, U4096> = Deblockator::new(
+            SizedBlocks(RefCell::new(Vec::new())),
+        )
+        .growth_policy(GrowthPolicy::Geometric {
+            factor: 2,
+            max: 16384,
+        });
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..20).map(|_| va.alloc(layout)).collect();
+            assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+
+            let mut sizes = Vec::new();
+            let mut block: *const Option<&mut HeapBlock> = va.first_block.get();
+            while let Some(ref b) = *block {
+                sizes.push(b.size);
+                block = &b.next;
+            }
+            assert_eq!(sizes, [4096, 8192, 16384, 16384]);
+            assert!(sizes[1] > sizes[0]);
+
+            for &ptr in &ptrs {
+                va.dealloc(ptr, layout);
+            }
+            assert_eq!(va.stats().heapblock_count, 1);
+        }
+        drop(va);
+    }
+
+    #[test]
+    #[cfg(feature = "zero-on-free")]
+    /// Check freed memory is cleared before being reused.
+    fn deblockator_zero_on_free() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        // poisoning overwrites the cleared memory with its own pattern
+        let cleared = |byte: u8| match cfg!(feature = "poison") {
+            true => byte != 0x5A,
+            false => byte == 0,
+        };
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            let c = va.alloc(layout);
+            for ptr in [a, b, c] {
+                ptr.write_bytes(0x5A, 64);
+            }
+
+            // reuse a hole in the middle of live allocations
+            va.dealloc(b, layout);
+            let d = va.alloc(layout);
+            assert_eq!(d, b);
+            assert!((0..64).all(|i| cleared(d.add(i).read())));
+
+            // reuse holes merged together
+            va.dealloc(c, layout);
+            va.dealloc(d, layout);
+            let large = Layout::from_size_align(128, 8).expect("bad layout");
+            let e = va.alloc(large);
+            assert_eq!(e, b);
+            assert!((0..128).all(|i| cleared(e.add(i).read())));
+
+            // large allocations are cleared before being given back
+            let layout = Layout::from_size_align(3000, 8).expect("bad layout");
+            let f = va.alloc(layout);
+            f.write_bytes(0x5A, 3000);
+            va.dealloc(f, layout);
+            assert!((0..4096).all(|i| f.add(i).read() == 0));
+            va.dealloc(e, large);
+            va.dealloc(a, Layout::from_size_align(64, 8).expect("bad layout"));
+        }
+    }
+
+    #[test]
+    /// Check the trace hook receives every event in order.
+    fn deblockator_trace_hook() {
+        static EVENTS: std::sync::Mutex<Vec<Event>> = std::sync::Mutex::new(Vec::new());
+        fn hook(event: Event) {
+            EVENTS.lock().unwrap().push(event);
+        }
+
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).retain_first_block(false);
+        va.set_trace_hook(hook);
+        let base = unsafe { (*va.block_allocator.get()).block(0) as usize };
+
+        unsafe {
+            let layout = Layout::from_size_align(32, 8).expect("bad layout");
+            let ptr = va.alloc(layout);
+            va.dealloc(ptr, layout);
+
+            let event = |kind, addr, size| Event { kind, addr, size };
+            assert_eq!(
+                *EVENTS.lock().unwrap(),
+                [
+                    event(EventKind::BlockCreate, base, 4096),
+                    event(EventKind::Alloc, ptr as usize, 32),
+                    event(EventKind::Dealloc, ptr as usize, 32),
+                    event(EventKind::BlockFree, base, 4096),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    /// Check allocations can be directed to the heapblock of a previous one.
+    fn deblockator_alloc_in() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            // fill the first heapblock, so that a second one is created
+            let big = Layout::from_size_align(1800, 8).expect("bad layout");
+            let a = va.alloc(big);
+            let b = va.alloc(big);
+            let c = va.alloc(big);
+            assert_eq!(va.block_id(c), Some(BlockId(mock.block(1) as usize)));
+
+            // a small allocation still fits next to `a`
+            let small = Layout::from_size_align(64, 8).expect("bad layout");
+            let id = va.block_id(a).expect("no heapblock");
+            let d = va.alloc_in(id, small);
+            let base = mock.block(0) as usize;
+            assert!(base <= d as usize && d as usize + 64 <= base + 4096);
+            assert_eq!(va.block_id(d), Some(id));
+
+            // an allocation that does not fit is not spilled elsewhere
+            assert!(va.alloc_in(id, big).is_null());
+            let large = Layout::from_size_align(2048, 8).expect("bad layout");
+            assert!(va.alloc_in(id, large).is_null());
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+
+            va.dealloc(d, small);
+            va.dealloc(c, big);
+            va.dealloc(b, big);
+            va.dealloc(a, big);
+
+            // the second heapblock was released
+            assert!(va
+                .alloc_in(BlockId(mock.block(1) as usize), small)
+                .is_null());
+        }
+    }
+
+    #[test]
+    /// Check the debug output summarizes the heap, unless the lock is held.
+    fn deblockator_debug() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new());
+        assert_eq!(
+            format!("{:?}", va),
+            "Deblockator { heapblock_count: 0, total_bytes: 0, used_bytes: 0, \
+             free_bytes: 0, large_allocation_count: 0 }"
+        );
+
+        unsafe {
+            let small = Layout::from_size_align(1500, 8).expect("bad layout");
+            va.alloc(small);
+            va.alloc(small);
+            va.alloc(small);
+            va.alloc(Layout::from_size_align(3000, 8).expect("bad layout"));
+        }
+        let debug = format!("{:?}", va);
+        assert!(debug.contains("heapblock_count: 2,"), "{}", debug);
+        assert!(debug.contains("total_bytes: 8192,"), "{}", debug);
+        assert!(debug.contains("large_allocation_count: 1 }"), "{}", debug);
+
+        let lock = va.mutex.lock().expect("poisoned");
+        assert_eq!(format!("{:?}", va), "Deblockator { <locked> }");
+        drop(lock);
+        assert_eq!(format!("{:?}", va), "Deblockator { <poisoned> }");
+    }
+
+    #[test]
+    /// Check no heapblock is allocated past the maximum count.
+    fn deblockator_max_block_count() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).max_block_count(2);
+        let mock = unsafe { &*va.block_allocator.get() };
+
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..4).map(|_| va.alloc(layout)).collect();
+            assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+
+            // a third heapblock would be needed
+            assert!(va.alloc(layout).is_null());
+            assert_eq!(va.try_alloc(layout), Err(AllocFailure::NoFit));
+            assert_eq!(mock.allocated.get(), [true, true, false]);
+
+            // large allocations are not capped
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let ptr = va.alloc(large);
+            assert_eq!(ptr, mock.block(2));
+            va.dealloc(ptr, large);
+
+            // room in the existing heapblocks can still be used
+            va.dealloc(ptrs[3], layout);
+            assert!(!va.alloc(layout).is_null());
+        }
+    }
+
+    #[test]
+    /// Check the scratch heapblock does not count towards the maximum count.
+    fn deblockator_max_block_count_scratch() {
+        let va: Deblockator<MockAlloc, U4096, U4096, U2048, U4096> =
+            Deblockator::new(MockAlloc::new()).max_block_count(1);
+        let mock = unsafe { &*va.block_allocator.get() };
+        let scratch: &'static mut [u8] = Box::leak(vec![0u8; 256].into_boxed_slice());
+        assert!(va.seed_scratch(scratch));
+
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let ptrs: Vec<_> = (0..2).map(|_| va.alloc(layout)).collect();
+            assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+            assert_eq!(mock.allocated.get(), [true, false, false]);
+            assert_eq!(va.try_alloc(layout), Err(AllocFailure::NoFit));
+
+            for ptr in ptrs {
+                va.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "tags"))] // tags are read at the end of the given layout
+    /// Check any number of large allocations are routed by address, and given back on drop.
+    fn deblockator_large_registry_full() {
+        /// Allocate blocks with the system allocator, tracking the live ones.
+        struct TrackedBlocks<'a>(&'a RefCell<Vec<(*mut u8, Layout)>>);
+
+        unsafe impl Allocator for TrackedBlocks<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                let ptr = std::alloc::System.allocate(layout)?;
+                self.0.borrow_mut().push((ptr.as_ptr() as *mut u8, layout));
+                Ok(ptr)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                let mut live = self.0.borrow_mut();
+                let i = live.iter().position(|&(p, _)| p == ptr.as_ptr());
+                let (_, allocated) = live.swap_remove(i.expect("unknown block"));
+                assert_eq!(layout, allocated);
+                std::alloc::System.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for TrackedBlocks<'_> {}
+
+        let live = RefCell::new(Vec::new());
+        {
+            let va: Deblockator<TrackedBlocks, U4096, U4096, U2048, U4096> =
+                Deblockator::new(TrackedBlocks(&live));
+            let layout = Layout::from_size_align(3000, 8).expect("bad layout");
+            unsafe {
+                let ptrs: Vec<_> = (0..100).map(|_| va.alloc(layout)).collect();
+                assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+                assert_eq!(live.borrow().len(), 100);
+                let registry = &*va.large_registry.get();
+                assert!(ptrs.iter().all(|&p| registry.contains(p as usize)));
+
+                // a layout that would not be large does not misroute the deallocation
+                let small = Layout::from_size_align(100, 8).expect("bad layout");
+                for &ptr in ptrs.iter().step_by(2) {
+                    va.dealloc(ptr, small);
+                }
+                assert_eq!(live.borrow().len(), 50);
+                assert_eq!(*va.large_count.get(), 50);
+            }
         }
+        // the remaining allocations are given back on drop
+        assert!(live.borrow().is_empty());
     }
 
     #[test]
@@ -0,0 +1,99 @@
+//! Optional capabilities of the underlying allocator.
+
+use core::alloc::Allocator;
+use core::ptr::NonNull;
+
+/// An allocator providing memory blocks to a [`Deblockator`](crate::Deblockator).
+///
+/// Every method has a default implementation, so any [`Allocator`] can be
+/// used to allocate heapblocks with an empty implementation:
+/// ```rust
+/// #![feature(allocator_api)]
+/// # use std::alloc::{AllocError, Allocator, Layout};
+/// # use std::ptr::NonNull;
+/// use deblockator::BlockAllocator;
+///
+/// struct MyAllocator;
+/// # unsafe impl Allocator for MyAllocator {
+/// #     fn allocate(&self, _: Layout) -> Result<NonNull<[u8]>, AllocError> { Err(AllocError) }
+/// #     unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+/// # }
+///
+/// impl BlockAllocator for MyAllocator {}
+/// ```
+pub trait BlockAllocator: Allocator {
+    /// Returns the number of bytes that can still be allocated, if known.
+    ///
+    /// Used to predict whether the heap can grow without actually
+    /// allocating anything.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+
+    /// Release the physical memory of a block about to be deallocated.
+    ///
+    /// Called right before a large allocation is given back, so that an
+    /// allocator keeping its mappings around after `deallocate` can still
+    /// return the pages to the system (e.g. with `madvise(MADV_DONTNEED)`).
+    /// Does nothing by default.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block of `len` bytes currently allocated by this
+    /// allocator.
+    unsafe fn decommit(&self, ptr: NonNull<u8>, len: usize) {
+        let _ = (ptr, len);
+    }
+
+    /// Check whether the blocks returned by `allocate` are always filled with zeros.
+    ///
+    /// This is the case for fresh pages mapped by most kernels. Zeroed
+    /// allocations then avoid clearing memory that was never written to.
+    /// Returns `false` by default.
+    fn zeroes_blocks(&self) -> bool {
+        false
+    }
+
+    /// Check whether `ptr` lies within a block currently allocated, if known.
+    ///
+    /// Used to recognize pointers into blocks the
+    /// [`Deblockator`](crate::Deblockator) does not know about, since its
+    /// heapblocks and live large allocations are recognized without it.
+    fn owns(&self, ptr: NonNull<u8>) -> Option<bool> {
+        let _ = ptr;
+        None
+    }
+}
+
+/// A block allocator with a default value usable in constant expressions.
+///
+/// [`Default::default`] cannot be called in a `const fn` on a stable
+/// toolchain, nor without the unstable `const_trait_impl` feature on a
+/// nightly one, so this trait provides the default value as an associated
+/// constant instead. It allows creating a
+/// [`Deblockator::new_default`](crate::Deblockator::new_default) in a
+/// `static` without naming the underlying allocator:
+/// ```rust
+/// #![feature(allocator_api)]
+/// # use std::alloc::{AllocError, Allocator, Layout};
+/// # use std::ptr::NonNull;
+/// use deblockator::{BlockAllocator, ConstDefault, Deblockator};
+///
+/// struct MyAllocator;
+/// # unsafe impl Allocator for MyAllocator {
+/// #     fn allocate(&self, _: Layout) -> Result<NonNull<[u8]>, AllocError> { Err(AllocError) }
+/// #     unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+/// # }
+///
+/// impl BlockAllocator for MyAllocator {}
+///
+/// impl ConstDefault for MyAllocator {
+///     const DEFAULT: Self = MyAllocator;
+/// }
+///
+/// static GLOBAL: Deblockator<MyAllocator> = Deblockator::new_default();
+/// ```
+pub trait ConstDefault: Sized {
+    /// The default value of the allocator.
+    const DEFAULT: Self;
+}
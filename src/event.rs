@@ -0,0 +1,102 @@
+//! Allocator events, and a bounded log of the most recent ones.
+//!
+//! The log is a fixed-size ring buffer living inside the allocator itself, so
+//! it can be used for post-mortem debugging on targets without any logger.
+//! The events can also be traced as they happen with a hook.
+
+/// The number of events kept by the event log.
+pub const EVENT_LOG_LEN: usize = 64;
+
+/// The kind of operation recorded in an [`Event`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A memory block was handed out to the user.
+    #[default]
+    Alloc,
+    /// A memory block was returned by the user.
+    Dealloc,
+    /// A new heapblock was obtained from the underlying allocator.
+    BlockCreate,
+    /// An empty heapblock was returned to the underlying allocator.
+    BlockFree,
+    /// A corrupted hole list was truncated at the given address.
+    Heal,
+}
+
+/// A single allocator event.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// The kind of operation.
+    pub kind: EventKind,
+    /// The address of the memory block concerned by the operation.
+    pub addr: usize,
+    /// The size of the memory block concerned by the operation.
+    pub size: usize,
+}
+
+/// A ring buffer of the last [`EVENT_LOG_LEN`] events.
+pub struct EventLog {
+    events: [Event; EVENT_LOG_LEN],
+    next: usize,
+    len: usize,
+}
+
+impl EventLog {
+    /// Create a new empty event log.
+    pub const fn new() -> Self {
+        EventLog {
+            events: [Event {
+                kind: EventKind::Alloc,
+                addr: 0,
+                size: 0,
+            }; EVENT_LOG_LEN],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Record an event, overwriting the oldest one if the log is full.
+    pub fn push(&mut self, kind: EventKind, addr: usize, size: usize) {
+        self.events[self.next] = Event { kind, addr, size };
+        self.next = (self.next + 1) % EVENT_LOG_LEN;
+        if self.len < EVENT_LOG_LEN {
+            self.len += 1;
+        }
+    }
+
+    /// Copy the most recent events to `buf`, oldest first.
+    ///
+    /// Returns the number of events written, which is at most `buf.len()`.
+    pub fn recent(&self, buf: &mut [Event]) -> usize {
+        let count = self.len.min(buf.len());
+        let start = (self.next + EVENT_LOG_LEN - count) % EVENT_LOG_LEN;
+        for (i, slot) in buf.iter_mut().take(count).enumerate() {
+            *slot = self.events[(start + i) % EVENT_LOG_LEN];
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Check the log keeps only the most recent events, in order.
+    fn eventlog_wraps() {
+        let mut log = EventLog::new();
+        for i in 0..EVENT_LOG_LEN + 3 {
+            log.push(EventKind::Alloc, i, 8);
+        }
+
+        let mut buf = [Event::default(); 4];
+        assert_eq!(log.recent(&mut buf), 4);
+        for (i, event) in buf.iter().enumerate() {
+            assert_eq!(event.addr, EVENT_LOG_LEN - 1 + i);
+        }
+
+        let mut buf = [Event::default(); EVENT_LOG_LEN * 2];
+        assert_eq!(log.recent(&mut buf), EVENT_LOG_LEN);
+        assert_eq!(buf[0].addr, 3);
+    }
+}
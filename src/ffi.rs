@@ -0,0 +1,313 @@
+//! C allocation functions backed by a Rust allocator.
+//!
+//! Unlike [`GlobalAlloc`], the C allocation functions do not receive the
+//! layout of the memory to free: each allocation is prefixed with a small
+//! header recording its layout. The [`c_allocator!`](crate::c_allocator)
+//! macro exports these functions under their C names.
+
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::cmp::max;
+use core::mem::align_of;
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+/// The alignment of the memory returned by [`malloc`], enough for any C type.
+pub const MALLOC_ALIGN: usize = 2 * size_of::<usize>();
+
+/// The header stored right before each allocation.
+#[derive(Clone, Copy)]
+struct Header {
+    size: usize,  // the size of the underlying allocation, header included.
+    align: usize, // the alignment of the underlying allocation.
+}
+
+impl Header {
+    /// Returns the offset of the allocation from the start of the underlying allocation.
+    fn offset(align: usize) -> usize {
+        max(size_of::<Header>(), align)
+    }
+
+    /// Returns the layout of the underlying allocation.
+    unsafe fn layout(&self) -> Layout {
+        Layout::from_size_align_unchecked(self.size, self.align)
+    }
+
+    /// Read the header of the allocation at `ptr`.
+    unsafe fn read(ptr: *mut u8) -> Header {
+        (ptr as *mut Header).sub(1).read()
+    }
+
+    /// Write the header of the allocation at `ptr`.
+    unsafe fn write(self, ptr: *mut u8) {
+        (ptr as *mut Header).sub(1).write(self)
+    }
+}
+
+/// Allocate `size` bytes aligned to [`MALLOC_ALIGN`].
+///
+/// # Safety
+///
+/// The memory must be released with [`free`] or [`realloc`] on the same allocator.
+pub unsafe fn malloc<G: GlobalAlloc>(alloc: &G, size: usize) -> *mut u8 {
+    memalign(alloc, MALLOC_ALIGN, size)
+}
+
+/// Allocate `size` bytes aligned to `align`, which must be a power of two.
+///
+/// Returns a null pointer if `align` is not a power of two, or if the memory
+/// could not be allocated.
+///
+/// # Safety
+///
+/// The memory must be released with [`free`] or [`realloc`] on the same allocator.
+pub unsafe fn memalign<G: GlobalAlloc>(alloc: &G, align: usize, size: usize) -> *mut u8 {
+    if !align.is_power_of_two() {
+        return null_mut();
+    }
+    let align = max(align, align_of::<Header>());
+    let offset = Header::offset(align);
+    let layout = match offset
+        .checked_add(size)
+        .and_then(|total| Layout::from_size_align(total, align).ok())
+    {
+        Some(layout) => layout,
+        None => return null_mut(),
+    };
+
+    let base = alloc.alloc(layout);
+    if base.is_null() {
+        return null_mut();
+    }
+    let ptr = base.add(offset);
+    Header {
+        size: layout.size(),
+        align,
+    }
+    .write(ptr);
+    ptr
+}
+
+/// Allocate `nmemb * size` bytes aligned to [`MALLOC_ALIGN`], and zero them.
+///
+/// # Safety
+///
+/// The memory must be released with [`free`] or [`realloc`] on the same allocator.
+pub unsafe fn calloc<G: GlobalAlloc>(alloc: &G, nmemb: usize, size: usize) -> *mut u8 {
+    let size = match nmemb.checked_mul(size) {
+        Some(size) => size,
+        None => return null_mut(),
+    };
+    let ptr = malloc(alloc, size);
+    if !ptr.is_null() {
+        ptr.write_bytes(0, size);
+    }
+    ptr
+}
+
+/// Release the memory at `ptr`. Does nothing if `ptr` is null.
+///
+/// # Safety
+///
+/// `ptr` must be null, or have been returned by one of the functions of
+/// this module on the same allocator and not freed since.
+pub unsafe fn free<G: GlobalAlloc>(alloc: &G, ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    let header = Header::read(ptr);
+    let base = ptr.sub(Header::offset(header.align));
+    alloc.dealloc(base, header.layout());
+}
+
+/// Resize the memory at `ptr` to `size` bytes, keeping its alignment.
+///
+/// Behaves as [`malloc`] if `ptr` is null, and as [`free`] if `size` is `0`,
+/// in which case a null pointer is returned. If the memory could not be
+/// resized, a null pointer is returned and `ptr` is left untouched.
+///
+/// # Safety
+///
+/// The same as [`free`].
+pub unsafe fn realloc<G: GlobalAlloc>(alloc: &G, ptr: *mut u8, size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return malloc(alloc, size);
+    } else if size == 0 {
+        free(alloc, ptr);
+        return null_mut();
+    }
+
+    let header = Header::read(ptr);
+    let offset = Header::offset(header.align);
+    let new_size = match offset.checked_add(size) {
+        Some(new_size) if Layout::from_size_align(new_size, header.align).is_ok() => new_size,
+        _ => return null_mut(),
+    };
+    let base = alloc.realloc(ptr.sub(offset), header.layout(), new_size);
+    if base.is_null() {
+        return null_mut();
+    }
+    let new_ptr = base.add(offset);
+    Header {
+        size: new_size,
+        align: header.align,
+    }
+    .write(new_ptr);
+    new_ptr
+}
+
+/// Returns the number of bytes usable at `ptr`, as requested on allocation.
+///
+/// # Safety
+///
+/// The same as [`free`], except that `ptr` must not be null.
+pub unsafe fn usable_size(ptr: *mut u8) -> usize {
+    let header = Header::read(ptr);
+    header.size - Header::offset(header.align)
+}
+
+/// Export the C allocation functions backed by the given static allocator.
+///
+/// This defines `malloc`, `free`, `realloc`, `calloc` and `memalign` in the
+/// current module, with the C ABI and unmangled names so that they replace
+/// the C library functions when linking. Test builds keep the names mangled
+/// so that the test harness still uses the system allocator.
+///
+/// ```rust,no_run
+/// #![feature(allocator_api)]
+/// # use std::alloc::{AllocError, Allocator, Layout};
+/// # use std::ptr::NonNull;
+/// #[macro_use]
+/// extern crate deblockator;
+///
+/// use deblockator::{BlockAllocator, Deblockator};
+///
+/// struct MyAllocator;
+/// # unsafe impl Allocator for MyAllocator {
+/// #     fn allocate(&self, _: Layout) -> Result<NonNull<[u8]>, AllocError> { Err(AllocError) }
+/// #     unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+/// # }
+/// impl BlockAllocator for MyAllocator {}
+///
+/// static ALLOCATOR: Deblockator<MyAllocator> = Deblockator::new(MyAllocator);
+/// c_allocator!(ALLOCATOR);
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! c_allocator {
+    ($alloc:path) => {
+        #[cfg_attr(not(test), no_mangle)]
+        pub unsafe extern "C" fn malloc(size: usize) -> *mut ::core::ffi::c_void {
+            $crate::ffi::malloc(&$alloc, size) as *mut _
+        }
+
+        #[cfg_attr(not(test), no_mangle)]
+        pub unsafe extern "C" fn free(ptr: *mut ::core::ffi::c_void) {
+            $crate::ffi::free(&$alloc, ptr as *mut u8)
+        }
+
+        #[cfg_attr(not(test), no_mangle)]
+        pub unsafe extern "C" fn realloc(
+            ptr: *mut ::core::ffi::c_void,
+            size: usize,
+        ) -> *mut ::core::ffi::c_void {
+            $crate::ffi::realloc(&$alloc, ptr as *mut u8, size) as *mut _
+        }
+
+        #[cfg_attr(not(test), no_mangle)]
+        pub unsafe extern "C" fn calloc(nmemb: usize, size: usize) -> *mut ::core::ffi::c_void {
+            $crate::ffi::calloc(&$alloc, nmemb, size) as *mut _
+        }
+
+        #[cfg_attr(not(test), no_mangle)]
+        pub unsafe extern "C" fn memalign(align: usize, size: usize) -> *mut ::core::ffi::c_void {
+            $crate::ffi::memalign(&$alloc, align, size) as *mut _
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::AllocError;
+    use core::alloc::Allocator;
+    use core::alloc::Layout;
+    use core::ffi::c_void;
+    use core::ptr::NonNull;
+    use std::alloc::System;
+
+    use typenum::consts::U1024;
+    use typenum::consts::U4096;
+
+    use super::super::BlockAllocator;
+    use super::super::Deblockator;
+
+    /// Allocate heapblocks with the system allocator.
+    struct SystemBlocks;
+
+    unsafe impl Allocator for SystemBlocks {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            System.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            System.deallocate(ptr, layout)
+        }
+    }
+
+    impl BlockAllocator for SystemBlocks {}
+
+    static ALLOCATOR: Deblockator<SystemBlocks, U4096, U4096, U1024, U4096> =
+        Deblockator::new(SystemBlocks);
+
+    c_allocator!(ALLOCATOR);
+
+    #[test]
+    /// Check memory allocated through the C functions can be resized and freed.
+    fn c_allocator_roundtrip() {
+        unsafe {
+            let ptr = malloc(100) as *mut u8;
+            assert!(!ptr.is_null());
+            assert!((ptr as usize).is_multiple_of(super::MALLOC_ALIGN));
+            for i in 0..100 {
+                ptr.add(i).write(i as u8);
+            }
+            assert_eq!(super::usable_size(ptr), 100);
+
+            // grow to a large allocation, then shrink back
+            let ptr = realloc(ptr as *mut c_void, 5000) as *mut u8;
+            assert!(!ptr.is_null());
+            assert_eq!(super::usable_size(ptr), 5000);
+            let ptr = realloc(ptr as *mut c_void, 50) as *mut u8;
+            assert!(!ptr.is_null());
+            for i in 0..50 {
+                assert_eq!(ptr.add(i).read(), i as u8);
+            }
+            free(ptr as *mut c_void);
+            free(core::ptr::null_mut());
+
+            let ptr = memalign(256, 100) as *mut u8;
+            assert!((ptr as usize).is_multiple_of(256));
+            free(ptr as *mut c_void);
+            assert!(memalign(3, 100).is_null());
+        }
+    }
+
+    #[test]
+    /// Check memory allocated with `calloc` is zeroed.
+    fn c_allocator_calloc() {
+        unsafe {
+            // dirty some memory first
+            let ptr = malloc(64) as *mut u8;
+            ptr.write_bytes(0xFF, 64);
+            free(ptr as *mut c_void);
+
+            let ptr = calloc(8, 8) as *mut u8;
+            assert!(!ptr.is_null());
+            for i in 0..64 {
+                assert_eq!(ptr.add(i).read(), 0);
+            }
+            free(ptr as *mut c_void);
+            assert!(calloc(usize::MAX, 2).is_null());
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Strategies choosing the hole an allocation is carved from.
+
+/// A strategy choosing the hole of a heapblock an allocation is carved from.
+///
+/// The holes of a heapblock are scanned in address order. A non-exhaustive
+/// strategy uses the first hole fitting the allocation, while an exhaustive
+/// one scans all the holes and uses the best one according to [`better`].
+///
+/// [`better`]: FitStrategy::better
+pub trait FitStrategy {
+    /// Whether all the holes are scanned before choosing one.
+    const EXHAUSTIVE: bool;
+
+    /// Returns whether a hole of `candidate` bytes is better than one of `best` bytes.
+    ///
+    /// Both holes fit the allocation. Only used by exhaustive strategies.
+    fn better(best: usize, candidate: usize) -> bool;
+}
+
+/// Use the first hole fitting the allocation.
+///
+/// This is the fastest strategy, but it tends to split large holes at the
+/// start of the heapblocks for small allocations.
+pub struct FirstFit;
+
+impl FitStrategy for FirstFit {
+    const EXHAUSTIVE: bool = false;
+
+    fn better(_best: usize, _candidate: usize) -> bool {
+        false
+    }
+}
+
+/// Use the smallest hole fitting the allocation.
+///
+/// This scans all the holes of each heapblock, but keeps large holes
+/// available for large allocations in mixed workloads.
+pub struct BestFit;
+
+impl FitStrategy for BestFit {
+    const EXHAUSTIVE: bool = true;
+
+    fn better(best: usize, candidate: usize) -> bool {
+        candidate < best
+    }
+}
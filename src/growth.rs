@@ -0,0 +1,66 @@
+//! Policies choosing the size of new heapblocks.
+
+use core::cmp::max;
+use core::cmp::min;
+
+/// A policy choosing the size of each new heapblock.
+///
+/// The sizes are always multiples of the block size `BS` of the
+/// [`Deblockator`](crate::Deblockator). See
+/// [`Deblockator::growth_policy`](crate::Deblockator::growth_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// Every heapblock is `BS` bytes large.
+    Fixed,
+    /// Every new heapblock is `factor` times larger than the previous one.
+    ///
+    /// The first heapblock is `BS` bytes large, and the sizes are capped to
+    /// `max` bytes, rounded down to a multiple of `BS`. This reduces the
+    /// number of calls to the underlying allocator for a growing heap.
+    Geometric {
+        /// The ratio between the sizes of two successive heapblocks.
+        factor: usize,
+        /// The maximum size of a heapblock.
+        max: usize,
+    },
+}
+
+impl GrowthPolicy {
+    /// Returns the size of the heapblock following one of `size` bytes.
+    pub fn next_size(&self, size: usize, block_size: usize) -> usize {
+        match *self {
+            GrowthPolicy::Fixed => block_size,
+            GrowthPolicy::Geometric { factor, max: cap } => {
+                let cap = max(cap - cap % block_size, block_size);
+                min(size.saturating_mul(factor), cap)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Check geometric sizes grow up to the cap, in multiples of the block size.
+    fn growthpolicy_next_size() {
+        assert_eq!(GrowthPolicy::Fixed.next_size(8192, 4096), 4096);
+
+        let policy = GrowthPolicy::Geometric {
+            factor: 2,
+            max: 20000,
+        };
+        assert_eq!(policy.next_size(4096, 4096), 8192);
+        assert_eq!(policy.next_size(8192, 4096), 16384);
+        assert_eq!(policy.next_size(16384, 4096), 16384);
+        assert_eq!(policy.next_size(usize::MAX, 4096), 16384);
+
+        // a cap below the block size still allows heapblocks
+        let policy = GrowthPolicy::Geometric {
+            factor: 2,
+            max: 1000,
+        };
+        assert_eq!(policy.next_size(4096, 4096), 4096);
+    }
+}
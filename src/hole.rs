@@ -5,7 +5,10 @@
 
 use core::alloc::AllocError;
 use core::alloc::Layout;
+use core::cmp::max;
+use core::cmp::min;
 use core::marker::PhantomData;
+use core::mem::align_of;
 use core::mem::size_of;
 use core::ptr::NonNull;
 
@@ -13,46 +16,137 @@ use typenum::consts::U1;
 use typenum::consts::U65536;
 use typenum::Unsigned;
 
+use super::fit::FirstFit;
+use super::fit::FitStrategy;
+use super::info::BlockInfo;
 use super::utils::align_up;
 
+/// The byte pattern freed memory is filled with.
+#[cfg(feature = "poison")]
+pub const POISON: u8 = 0xDE;
+
+/// The byte pattern the free memory of a new heap block is filled with.
+#[cfg(feature = "poison")]
+pub const FRESH: u8 = 0xCD;
+
+/// The reason an allocation could not be carved from a heap block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoleError {
+    /// The layout is smaller than [`HeapBlock::min_size`].
+    TooSmall,
+    /// A hole is large enough for the layout, but not once aligned.
+    BadAlignment,
+    /// No hole is large enough for the layout, or the scan limit was reached.
+    NoFit,
+}
+
+impl From<HoleError> for AllocError {
+    fn from(_: HoleError) -> Self {
+        AllocError
+    }
+}
+
 /// A heap block.
 pub struct HeapBlock<BS = U65536>
 where
     BS: 'static + Unsigned,
 {
     __block_size: PhantomData<BS>,
+    pub size: usize, // the total size of the heap block, header included.
     pub next: Option<&'static mut HeapBlock<BS>>, // a reference to the next heap block.
-    pub first: Hole,                              // a reference to the next hole in this heap.
+    pub first: Hole, // a reference to the next hole in this heap.
+    pub pristine: bool, // whether the holes are zeroed, except for their headers.
+    pub grown: bool, // whether the heap block is a single allocation larger than `BS`.
+    #[cfg(feature = "hardened")]
+    checksum: usize, // a checksum of the other fields.
 }
 
 impl<BS> HeapBlock<BS>
 where
     BS: Unsigned,
 {
-    /// Create a new heap block stored at the given location.
-    /// FIXME: use constant block size ?
-    pub unsafe fn new(block_ptr: NonNull<HeapBlock>) -> &'static mut HeapBlock {
+    /// Create a new heap block of `size` bytes stored at the given location.
+    ///
+    /// `zeroed` tells whether the memory of the block is known to be filled
+    /// with zeros, in which case the heap block is pristine until memory is
+    /// deallocated in it.
+    pub unsafe fn new(
+        block_ptr: NonNull<HeapBlock>,
+        size: usize,
+        zeroed: bool,
+    ) -> &'static mut HeapBlock {
         // The first hole comes right after the HeapBlock data in the
-        // block, so we shift the block_ptr offset by size_of::<HeapBlock>()
-        let hole_ptr = block_ptr.as_ptr().add(1) as *mut Hole; // FIXME ?
+        // block, so we shift the block_ptr offset by size_of::<HeapBlock>(),
+        // which keeps the hole aligned since the header is made of words
+        let hole_ptr = block_ptr.as_ptr().add(1) as *mut Hole;
 
         // Write the hole data
         hole_ptr.write(Hole {
-            size: BS::to_usize() - size_of::<HeapBlock>(),
+            size: size - size_of::<HeapBlock>(),
             next: None,
         });
 
         // Write the heap block data
         block_ptr.as_ptr().write(HeapBlock {
             __block_size: PhantomData,
+            size,
             next: None,
             first: Hole {
                 size: 0,
                 next: Some(&mut *hole_ptr),
             },
+            pristine: zeroed && cfg!(not(feature = "poison")),
+            grown: false,
+            #[cfg(feature = "hardened")]
+            checksum: 0,
         });
 
-        &mut *block_ptr.as_ptr()
+        #[cfg(feature = "poison")]
+        (*hole_ptr).fill(FRESH);
+
+        let block = &mut *block_ptr.as_ptr();
+        block.seal();
+        block
+    }
+
+    /// Compute the checksum of the heap block header.
+    #[cfg(feature = "hardened")]
+    fn compute_checksum(&self) -> usize {
+        let addr = self as *const Self as usize;
+        let next = self
+            .next
+            .as_ref()
+            .map_or(0, |b| *b as *const HeapBlock<BS> as usize);
+        let first = self
+            .first
+            .next
+            .as_ref()
+            .map_or(0, |h| *h as *const Hole as usize);
+        (addr ^ 0x5EA1_ED00).rotate_left(7)
+            ^ self.size.rotate_left(13)
+            ^ next.rotate_left(19)
+            ^ first.rotate_left(31)
+            ^ self.grown as usize
+    }
+
+    /// Update the checksum of the heap block header after a modification.
+    #[inline]
+    pub fn seal(&mut self) {
+        #[cfg(feature = "hardened")]
+        {
+            self.checksum = self.compute_checksum();
+        }
+    }
+
+    /// Panic if the heap block header does not match its checksum.
+    #[inline]
+    pub fn verify(&self) {
+        #[cfg(feature = "hardened")]
+        assert!(
+            self.checksum == self.compute_checksum(),
+            "heap block header corrupted at {:p}",
+            self
+        );
     }
 
     /// Searches the list for a big enough hole. A hole is big enough if it can hold an allocation
@@ -62,20 +156,237 @@ where
     ///
     /// This function uses the “first fit” strategy, so it uses the first hole that is big
     /// enough. Thus the runtime is in O(n) but it should be reasonably fast for small allocations.
-    pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
-        assert!(layout.size() >= Self::min_size());
+    ///
+    /// The number of holes examined is added to `scanned`.
+    pub fn allocate_first_fit(
+        &mut self,
+        layout: Layout,
+        scanned: &mut usize,
+    ) -> Result<NonNull<u8>, HoleError> {
+        self.allocate_with::<FirstFit>(layout, scanned, usize::MAX)
+    }
 
-        allocate_first_fit(&mut self.first, layout).map(|allocation| {
+    /// Allocate `layout` from the hole chosen by the strategy `F`.
+    ///
+    /// The number of holes examined is added to `scanned`, and the search
+    /// stops once it reaches `max_scan`.
+    pub fn allocate_with<F: FitStrategy>(
+        &mut self,
+        layout: Layout,
+        scanned: &mut usize,
+        max_scan: usize,
+    ) -> Result<NonNull<u8>, HoleError> {
+        if layout.size() < Self::min_size() {
+            return Err(HoleError::TooSmall);
+        }
+
+        let result = match F::EXHAUSTIVE {
+            false => allocate_first_fit(&mut self.first, layout, scanned, max_scan),
+            true => allocate_best_fit::<F>(&mut self.first, layout, scanned, max_scan),
+        };
+        let result = result.map(|allocation| {
             if let Some(padding) = allocation.front_padding {
                 deallocate(&mut self.first, padding.addr, padding.size);
             }
             if let Some(padding) = allocation.back_padding {
                 deallocate(&mut self.first, padding.addr, padding.size);
             }
+            // the header of the hole the allocation was carved from is stale
+            #[cfg(feature = "zero-on-free")]
+            unsafe {
+                (allocation.info.addr as *mut u8).write_bytes(0, size_of::<Hole>())
+            };
             NonNull::new(allocation.info.addr as *mut u8).unwrap()
+        });
+        self.seal();
+        result
+    }
+
+    /// Truncate the hole list at the first hole not lying within the heap block.
+    ///
+    /// A hole is considered invalid if it is misaligned, overlaps the previous
+    /// hole, or extends past the end of the heap block. The memory described
+    /// by the truncated holes is leaked. Returns the address of the invalid
+    /// hole, if any.
+    pub fn heal(&mut self) -> Option<usize> {
+        let end = self.end();
+        let mut min_addr = self as *const Self as usize + size_of::<HeapBlock>();
+        let mut link: *mut Option<&'static mut Hole> = &mut self.first.next;
+        unsafe {
+            while let Some(ref mut hole) = *link {
+                let addr = *hole as *const Hole as usize;
+                let valid = addr >= min_addr
+                    && addr.is_multiple_of(align_of::<Hole>())
+                    && addr
+                        .checked_add(size_of::<Hole>())
+                        .is_some_and(|hole_end| hole_end <= end)
+                    && hole.size >= Self::min_size()
+                    && hole.size <= end - addr;
+                if !valid {
+                    *link = None;
+                    self.seal();
+                    return Some(addr);
+                }
+                min_addr = addr + hole.size;
+                link = &mut hole.next;
+            }
+        }
+        None
+    }
+
+    /// Check the integrity of the hole list, describing the first violation.
+    ///
+    /// The holes must be sorted by address without overlapping, aligned,
+    /// at least [`min_size`](Self::min_size) bytes large, and lie
+    /// within the heap block, past its header.
+    #[cfg(any(test, feature = "validate"))]
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let end = self.end();
+        let mut min_addr = self as *const Self as usize + size_of::<HeapBlock>();
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            let info = h.info();
+            if !info.addr.is_multiple_of(align_of::<Hole>()) {
+                return Err("misaligned hole");
+            } else if info.addr < min_addr {
+                return Err("hole overlapping the header or the previous hole");
+            } else if info.size < Self::min_size() {
+                return Err("hole too small to hold its header");
+            } else if info.size > end - info.addr {
+                return Err("hole extending past the end of the heap block");
+            }
+            min_addr = info.addr + info.size;
+            hole = &h.next;
+        }
+        Ok(())
+    }
+
+    /// Check whether one of the holes of this heap block can fit `layout`.
+    ///
+    /// This performs the same search as `allocate_first_fit`, without
+    /// modifying the heap block.
+    pub fn fits(&self, layout: Layout) -> bool {
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            if split_hole(h.info(), layout).is_ok() {
+                return true;
+            }
+            hole = &h.next;
+        }
+        false
+    }
+
+    /// Check whether a fresh heap block of `block_size` bytes can fit `layout`.
+    ///
+    /// The heap block is assumed to be aligned to `block_align`. If the
+    /// required alignment is stricter than the block alignment, every
+    /// possible offset of the block base is tried, so that `true` is returned
+    /// only if the allocation is guaranteed to succeed.
+    pub fn fits_fresh(layout: Layout, block_size: usize, block_align: usize) -> bool {
+        let header = size_of::<HeapBlock>();
+        let offsets = max(layout.align() / block_align, 1);
+        (0..offsets).all(|i| {
+            let hole = HoleInfo {
+                addr: i * block_align + header,
+                size: block_size - header,
+            };
+            split_hole(hole, layout).is_ok()
         })
     }
 
+    /// Returns the number of bytes available for allocations in a fresh heap block.
+    pub fn capacity() -> usize {
+        BS::to_usize() - size_of::<HeapBlock>()
+    }
+
+    /// Returns the address of the end of this heap block.
+    pub fn end(&self) -> usize {
+        self as *const Self as usize + self.size
+    }
+
+    /// Check whether no memory is allocated in this heap block.
+    pub fn is_empty(&self) -> bool {
+        self.free_bytes() == self.size - size_of::<HeapBlock>()
+    }
+
+    /// Describe this heap block.
+    pub fn info(&self) -> BlockInfo {
+        let mut info = BlockInfo {
+            base: self as *const Self as usize,
+            size: self.size,
+            ..BlockInfo::default()
+        };
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            info.free_bytes += h.size;
+            info.holes += 1;
+            info.largest_hole = max(info.largest_hole, h.size);
+            hole = &h.next;
+        }
+        info
+    }
+
+    /// Returns the total size of the holes in this heap block.
+    pub fn free_bytes(&self) -> usize {
+        let mut free = 0;
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            free += h.size;
+            hole = &h.next;
+        }
+        free
+    }
+
+    /// Count the holes of this heap block in `buckets`, by power of two.
+    ///
+    /// Bucket `i` counts the holes of `2^i` inclusive to `2^(i+1)` exclusive
+    /// bytes, and the last bucket all larger ones.
+    pub fn hole_histogram(&self, buckets: &mut [u64]) {
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            let log2 = (usize::BITS - 1 - h.size.leading_zeros()) as usize;
+            if let Some(bucket) = buckets.len().checked_sub(1).map(|last| min(log2, last)) {
+                buckets[bucket] += 1;
+            }
+            hole = &h.next;
+        }
+    }
+
+    /// Check whether `addr` lies within one of the holes of this heap block.
+    pub fn in_hole(&self, addr: usize) -> bool {
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            let info = h.info();
+            if info.addr <= addr && addr < info.addr + info.size {
+                return true;
+            }
+            hole = &h.next;
+        }
+        false
+    }
+
+    /// Returns the first span of allocated memory starting at or after `from`.
+    ///
+    /// The span is returned as an address and a size. Adjacent allocations
+    /// are returned as a single span.
+    pub fn used_span(&self, from: usize) -> Option<(usize, usize)> {
+        let end = self.end();
+        let mut start = self as *const Self as usize + size_of::<HeapBlock>();
+        let mut hole = &self.first.next;
+        loop {
+            let span_end = match *hole {
+                Some(ref h) => h.info().addr,
+                None => end,
+            };
+            if span_end > start && start >= from {
+                return Some((start, span_end - start));
+            }
+            let h = hole.as_ref()?;
+            start = span_end + h.size;
+            hole = &h.next;
+        }
+    }
+
     /// Returns the minimal allocation size.
     ///
     /// Smaller allocations or deallocations are not allowed.
@@ -91,18 +402,164 @@ where
     /// block is adjacent to another free block, the blocks are merged again.
     /// This operation is in `O(n)` since the list needs to be sorted by address.
     pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        deallocate(&mut self.first, ptr.as_ptr() as usize, layout.size())
+        self.pristine = false;
+        deallocate(&mut self.first, ptr.as_ptr() as usize, layout.size());
+        #[cfg(feature = "zero-on-free")]
+        self.clear_freed(ptr.as_ptr() as usize, layout.size());
+        #[cfg(feature = "poison")]
+        self.poison_hole_at(ptr.as_ptr() as usize);
+        self.seal();
+    }
+
+    /// Resize the allocation of `size` bytes at `addr` to `new_size` bytes, without moving it.
+    ///
+    /// Growing takes the missing bytes from the hole right after the
+    /// allocation, and shrinking gives the tail of the allocation back as a
+    /// hole, coalesced with the hole right after the allocation. A tail too
+    /// small to hold a hole is only reclaimed by extending that hole
+    /// backwards. Returns `false`, leaving the heap block untouched, when the
+    /// hole after the allocation is missing or too small, or when a remainder
+    /// would be too small to hold a hole.
+    pub unsafe fn resize_in_place(&mut self, addr: usize, size: usize, new_size: usize) -> bool {
+        if new_size <= size {
+            let tail = size - new_size;
+            if tail == 0 {
+                return true;
+            } else if tail >= Self::min_size() {
+                let layout = Layout::from_size_align_unchecked(tail, 1);
+                self.deallocate(NonNull::new_unchecked((addr + new_size) as *mut u8), layout);
+                return true;
+            }
+        }
+
+        let end = addr + size;
+        let mut link: *mut Option<&'static mut Hole> = &mut self.first.next;
+        while let Some(ref mut hole) = *link {
+            let info = hole.info();
+            if info.addr < end {
+                link = &mut hole.next;
+                continue;
+            } else if info.addr == end && new_size < size {
+                // move the header of the next hole back over the tail
+                let next = hole.next.take();
+                #[cfg(feature = "zero-on-free")]
+                clear_header(end);
+                let moved = (addr + new_size) as *mut Hole;
+                moved.write(Hole {
+                    size: info.size + size - new_size,
+                    next,
+                });
+                *link = Some(&mut *moved);
+                self.pristine = false;
+                #[cfg(feature = "poison")]
+                self.poison_hole_at(addr + new_size);
+                self.seal();
+                return true;
+            } else if new_size < size {
+                return false;
+            }
+            let extra = new_size - size;
+            let rest = info.size.wrapping_sub(extra);
+            if info.addr != end || info.size < extra || (rest != 0 && rest < Self::min_size()) {
+                return false;
+            }
+            let next = hole.next.take();
+            *link = match rest {
+                0 => next,
+                _ => {
+                    let moved = (end + extra) as *mut Hole;
+                    moved.write(Hole { size: rest, next });
+                    Some(&mut *moved)
+                }
+            };
+            self.seal();
+            return true;
+        }
+        false
+    }
+
+    /// Clear the `size` bytes freed at `addr`, sparing the header of a hole starting there.
+    #[cfg(feature = "zero-on-free")]
+    unsafe fn clear_freed(&mut self, addr: usize, size: usize) {
+        let mut start = addr;
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            let info = h.info();
+            if info.addr >= addr {
+                if info.addr == addr {
+                    start += size_of::<Hole>();
+                }
+                break;
+            }
+            hole = &h.next;
+        }
+        (start as *mut u8).write_bytes(0, addr + size - start);
+    }
+
+    /// Poison the hole containing `addr`, after it was freed or merged.
+    #[cfg(feature = "poison")]
+    fn poison_hole_at(&mut self, addr: usize) {
+        let mut hole = &mut self.first.next;
+        while let Some(ref mut h) = *hole {
+            let info = h.info();
+            if info.addr <= addr && addr < info.addr + info.size {
+                return h.poison();
+            }
+            hole = &mut h.next;
+        }
+    }
+
+    /// Check that the holes of this heap block are still filled with [`POISON`] or [`FRESH`].
+    ///
+    /// Returns the address of the first hole that was written to.
+    #[cfg(feature = "poison")]
+    pub fn check_poison(&self) -> Result<(), *mut u8> {
+        let mut hole = &self.first.next;
+        while let Some(ref h) = *hole {
+            if !h.is_poisoned() {
+                return Err(*h as *const Hole as *mut u8);
+            }
+            hole = &h.next;
+        }
+        Ok(())
     }
 
     /// Check if the given pointer maps to a memory location that begins in the `HeapBlock`.
-    pub unsafe fn contains<T>(&self, ptr: *const T) -> bool {
-        let self_ptr = self as *const Self as *const u8;
-        let that_ptr = ptr as *const u8;
-        (self_ptr <= that_ptr) && (that_ptr <= self_ptr.add(BS::to_usize()))
+    pub fn contains<T>(&self, ptr: *const T) -> bool {
+        let that_ptr = ptr as usize;
+        (self as *const Self as usize <= that_ptr) && (that_ptr < self.end())
+    }
+
+    /// Merge the heap block `other`, starting right at the end of this one, into this one.
+    ///
+    /// The header of `other` and all its holes are added to the holes of
+    /// this heap block, which is extended to cover both.
+    pub unsafe fn absorb(&mut self, other: &'static mut HeapBlock) {
+        debug_assert_eq!(self.end(), other as *const HeapBlock as usize);
+
+        // read the holes of the other block before overwriting its header
+        let mut next = other.first.next.take();
+        let (base, size) = (other as *mut HeapBlock as usize, other.size);
+        deallocate(&mut self.first, base, size_of::<HeapBlock>());
+        while let Some(hole) = next {
+            next = hole.next.take();
+            let info = hole.info();
+            deallocate(&mut self.first, info.addr, info.size);
+        }
+        #[cfg(feature = "poison")]
+        self.poison_hole_at(base);
+
+        self.size += size;
+        self.pristine = false;
+        self.seal();
     }
 }
 
 /// A hole in a heap block.
+///
+/// Holes are stored at the start of the free memory they describe, so the
+/// free list costs no memory besides the heap block header. This is why
+/// allocations are never smaller than [`HeapBlock::min_size`].
 pub struct Hole {
     pub size: usize,
     pub next: Option<&'static mut Hole>,
@@ -116,6 +573,40 @@ impl Hole {
             size: self.size,
         }
     }
+
+    /// Returns the bytes of the hole following its header.
+    #[cfg(feature = "poison")]
+    fn body(&self) -> *mut [u8] {
+        let start = (self as *const Self as *mut u8).wrapping_add(size_of::<Hole>());
+        core::ptr::slice_from_raw_parts_mut(start, self.size - size_of::<Hole>())
+    }
+
+    /// Fill the hole with [`POISON`], leaving its header untouched.
+    #[cfg(feature = "poison")]
+    fn poison(&mut self) {
+        self.fill(POISON)
+    }
+
+    /// Fill the hole with `pattern`, leaving its header untouched.
+    #[cfg(feature = "poison")]
+    fn fill(&mut self, pattern: u8) {
+        unsafe { (*self.body()).fill(pattern) }
+    }
+
+    /// Check whether the hole is still filled with either [`POISON`] or [`FRESH`].
+    ///
+    /// A hole is filled with [`FRESH`] until a deallocation merges into it.
+    #[cfg(feature = "poison")]
+    fn is_poisoned(&self) -> bool {
+        let body = unsafe { &*self.body() };
+        match body.first() {
+            Some(&pattern) if pattern == POISON || pattern == FRESH => {
+                body.iter().all(|&b| b == pattern)
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
 }
 
 /// Basic information about a hole.
@@ -134,13 +625,18 @@ struct Allocation {
 }
 
 /// Splits the given hole into `(front_padding, hole, back_padding)` if it's big enough to allocate
-/// `required_layout.size()` bytes with the `required_layout.align()`. Else `None` is returned.
+/// `required_layout.size()` bytes with the `required_layout.align()`. Else an error is returned,
+/// `BadAlignment` if the hole would be large enough without the alignment padding.
 /// Front padding occurs if the required alignment is higher than the hole's alignment. Back
 /// padding occurs if the required size is smaller than the size of the aligned hole. All padding
 /// must be at least `HoleList::min_size()` big or the hole is unusable.
-fn split_hole(hole: HoleInfo, required_layout: Layout) -> Option<Allocation> {
+fn split_hole(hole: HoleInfo, required_layout: Layout) -> Result<Allocation, HoleError> {
     let required_size = required_layout.size();
     let required_align = required_layout.align();
+    let misfit = |front_padding: &Option<HoleInfo>| match front_padding {
+        Some(_) if hole.size >= required_size => HoleError::BadAlignment,
+        _ => HoleError::NoFit,
+    };
 
     let (aligned_addr, front_padding) = if hole.addr == align_up(hole.addr, required_align) {
         // hole has already the required alignment
@@ -160,7 +656,7 @@ fn split_hole(hole: HoleInfo, required_layout: Layout) -> Option<Allocation> {
     let aligned_hole = {
         if aligned_addr + required_size > hole.addr + hole.size {
             // hole is too small
-            return None;
+            return Err(misfit(&front_padding));
         }
         HoleInfo {
             addr: aligned_addr,
@@ -173,7 +669,7 @@ fn split_hole(hole: HoleInfo, required_layout: Layout) -> Option<Allocation> {
         None
     } else if aligned_hole.size - required_size < HeapBlock::<U1>::min_size() {
         // we can't use this hole since its remains would form a new, too small hole
-        return None;
+        return Err(misfit(&front_padding));
     } else {
         // the hole is bigger than necessary, so there is some padding behind the allocation
         Some(HoleInfo {
@@ -182,7 +678,7 @@ fn split_hole(hole: HoleInfo, required_layout: Layout) -> Option<Allocation> {
         })
     };
 
-    Some(Allocation {
+    Ok(Allocation {
         info: HoleInfo {
             addr: aligned_hole.addr,
             size: required_size,
@@ -198,31 +694,85 @@ fn split_hole(hole: HoleInfo, required_layout: Layout) -> Option<Allocation> {
 /// the allocation. This padding is returned as part of the `Allocation`. The caller must take
 /// care of freeing it again.
 /// This function uses the “first fit” strategy, so it breaks as soon as a big enough hole is
-/// found (and returns it).
-fn allocate_first_fit(mut previous: &mut Hole, layout: Layout) -> Result<Allocation, AllocError> {
+/// found (and returns it). The number of holes examined is added to `scanned`, and the search
+/// is abandoned once it reaches `max_scan`. On failure, `BadAlignment` is returned if any hole
+/// was only rejected because of the alignment.
+fn allocate_first_fit(
+    mut previous: &mut Hole,
+    layout: Layout,
+    scanned: &mut usize,
+    max_scan: usize,
+) -> Result<Allocation, HoleError> {
+    let mut error = HoleError::NoFit;
     loop {
-        let allocation: Option<Allocation> = previous
-            .next
-            .as_mut()
-            .and_then(|current| split_hole(current.info(), layout));
+        if *scanned >= max_scan {
+            return Err(error);
+        }
+        let allocation: Option<Result<Allocation, HoleError>> =
+            previous.next.as_mut().map(|current| {
+                *scanned += 1;
+                split_hole(current.info(), layout)
+            });
         match allocation {
-            Some(allocation) => {
+            Some(Ok(allocation)) => {
                 // hole is big enough, so remove it from the list by updating the previous pointer
                 previous.next = previous.next.as_mut().unwrap().next.take();
                 return Ok(allocation);
             }
-            None if previous.next.is_some() => {
+            Some(Err(e)) => {
                 // try next hole
+                if e == HoleError::BadAlignment {
+                    error = e;
+                }
                 previous = move_helper(previous).next.as_mut().unwrap();
             }
             None => {
                 // this was the last hole, so no hole is big enough -> allocation not possible
-                return Err(AllocError);
+                return Err(error);
             }
         }
     }
 }
 
+/// Searches the list starting at the next hole of `previous` for the best hole according to the
+/// strategy `F`, and allocates from it like `allocate_first_fit`. The number of holes examined is
+/// added to `scanned`, and the best hole found so far is used once it reaches `max_scan`.
+fn allocate_best_fit<F: FitStrategy>(
+    mut previous: &mut Hole,
+    layout: Layout,
+    scanned: &mut usize,
+    max_scan: usize,
+) -> Result<Allocation, HoleError> {
+    let mut best: Option<HoleInfo> = None;
+    let mut error = HoleError::NoFit;
+    let mut hole = &previous.next;
+    while let Some(ref h) = *hole {
+        if *scanned >= max_scan {
+            break;
+        }
+        *scanned += 1;
+        let info = h.info();
+        match split_hole(info, layout) {
+            Ok(_) if best.is_none_or(|b| F::better(b.size, info.size)) => best = Some(info),
+            Ok(_) => {}
+            Err(e) if e == HoleError::BadAlignment => error = e,
+            Err(_) => {}
+        }
+        hole = &h.next;
+    }
+
+    // remove the best hole from the list
+    let best = best.ok_or(error)?;
+    loop {
+        let current = previous.next.as_ref().unwrap().info();
+        if current.addr == best.addr {
+            previous.next = previous.next.as_mut().unwrap().next.take();
+            return split_hole(current, layout);
+        }
+        previous = move_helper(previous).next.as_mut().unwrap();
+    }
+}
+
 /// Frees the allocation given by `(addr, size)`. It starts at the given hole and walks the list to
 /// find the correct place (the list is sorted by address).
 fn deallocate(mut hole: &mut Hole, addr: usize, mut size: usize) {
@@ -251,6 +801,16 @@ fn deallocate(mut hole: &mut Hole, addr: usize, mut size: usize) {
         // get information about the next block
         let next_hole_info = hole.next.as_ref().map(|next| next.info());
 
+        // a freed block ending within the next hole was (partly) freed already
+        #[cfg(feature = "dealloc-checks")]
+        if let Some(next) = next_hole_info {
+            assert!(
+                next.addr <= addr || addr + size <= next.addr,
+                "invalid deallocation: freed memory overlaps the hole at {:#x}",
+                next.addr
+            );
+        }
+
         match next_hole_info {
             Some(next) if hole_addr + hole.size == addr && addr + size == next.addr => {
                 // block fills the gap between this hole and the next hole
@@ -259,6 +819,8 @@ fn deallocate(mut hole: &mut Hole, addr: usize, mut size: usize) {
 
                 hole.size += size + next.size; // merge the F and Y blocks to this X block
                 hole.next = hole.next.as_mut().unwrap().next.take(); // remove the Y block
+                #[cfg(feature = "zero-on-free")]
+                clear_header(next.addr);
             }
             _ if hole_addr + hole.size == addr => {
                 // block is right behind this hole but there is used memory after it
@@ -278,6 +840,8 @@ fn deallocate(mut hole: &mut Hole, addr: usize, mut size: usize) {
 
                 hole.next = hole.next.as_mut().unwrap().next.take(); // remove the Y block
                 size += next.size; // free the merged F/Y block in next iteration
+                #[cfg(feature = "zero-on-free")]
+                clear_header(next.addr);
                 continue;
             }
             Some(next) if next.addr <= addr => {
@@ -312,6 +876,12 @@ fn deallocate(mut hole: &mut Hole, addr: usize, mut size: usize) {
     }
 }
 
+/// Clear the header of a hole merged into the previous one.
+#[cfg(feature = "zero-on-free")]
+fn clear_header(addr: usize) {
+    unsafe { (addr as *mut u8).write_bytes(0, size_of::<Hole>()) }
+}
+
 /// Identity function to ease moving of references.
 ///
 /// By default, references are reborrowed instead of moved (equivalent to `&mut *reference`). This
@@ -325,6 +895,7 @@ fn move_helper<T>(x: T) -> T {
 
 #[cfg(test)]
 mod tests {
+    use super::super::fit::BestFit;
     use super::*;
     use typenum::U4096;
 
@@ -334,7 +905,7 @@ mod tests {
         unsafe {
             let mut block = [0u8; 4096];
             let addr = NonNull::new_unchecked(block[..].as_mut_ptr());
-            let block = HeapBlock::<U4096>::new(addr.cast());
+            let block = HeapBlock::<U4096>::new(addr.cast(), 4096, false);
 
             assert_eq!(block.first.size, 0);
             assert!(block.first.next.is_some());
@@ -342,23 +913,52 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "poison")]
+    /// Check new and freed memory are filled with their patterns, sparing the hole headers.
+    fn heapblock_poison() {
+        #[repr(C, align(4096))]
+        struct Page([u8; 4096]);
+
+        unsafe {
+            let mut page = Page([0; 4096]);
+            let base = page.0.as_mut_ptr();
+            let block = HeapBlock::<U4096>::new(NonNull::new_unchecked(base).cast(), 4096, false);
+            let body = size_of::<HeapBlock>() + size_of::<Hole>();
+            assert!((body..4096).all(|i| *base.add(i) == FRESH));
+
+            let layout = Layout::from_size_align_unchecked(64, 8);
+            let ptr = block
+                .allocate_first_fit(layout, &mut 0)
+                .expect("could not allocate");
+            ptr.as_ptr().write_bytes(0x42, 64);
+            block.deallocate(ptr, layout);
+
+            let hole = block.first.next.as_ref().expect("no hole");
+            assert_eq!(*hole as *const Hole as *mut u8, ptr.as_ptr());
+            assert_eq!(hole.size, 4096 - size_of::<HeapBlock>());
+            assert!((body..4096).all(|i| *base.add(i) == POISON));
+            assert_eq!(block.check_poison(), Ok(()));
+        }
+    }
+
     #[test]
     /// Check successive allocs / deallocs take place at the same adress.
     fn heapblock_alloc_dealloc() {
         unsafe {
             let mut block = [0u8; 4096];
             let addr = NonNull::new_unchecked(block.as_mut().as_mut_ptr()).cast();
-            let block = HeapBlock::<U4096>::new(addr);
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
             let layout = Layout::from_size_align_unchecked(32, 1);
 
-            if let Ok(alloc) = block.allocate_first_fit(layout) {
+            if let Ok(alloc) = block.allocate_first_fit(layout, &mut 0) {
                 assert_eq!(alloc.cast().as_ptr(), addr.as_ptr().add(1));
                 block.deallocate(alloc, Layout::from_size_align_unchecked(32, 1));
             } else {
                 panic!("Could not allocate block.")
             }
 
-            if let Ok(alloc) = block.allocate_first_fit(layout) {
+            if let Ok(alloc) = block.allocate_first_fit(layout, &mut 0) {
                 assert_eq!(alloc.cast().as_ptr(), addr.as_ptr().add(1));
             } else {
                 panic!("Could not allocate block.")
@@ -366,4 +966,175 @@ mod tests {
         }
     }
 
+    #[test]
+    /// Check the reason of an allocation failure is reported.
+    fn heapblock_hole_error() {
+        #[repr(C, align(4096))]
+        struct Page([u8; 4096]);
+
+        unsafe {
+            let mut page = Page([0; 4096]);
+            let addr = NonNull::new_unchecked(page.0.as_mut_ptr()).cast();
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
+            let capacity = HeapBlock::<U4096>::capacity();
+
+            let tiny = Layout::from_size_align_unchecked(HeapBlock::<U4096>::min_size() - 1, 1);
+            assert_eq!(
+                block.allocate_first_fit(tiny, &mut 0),
+                Err(HoleError::TooSmall)
+            );
+
+            let huge = Layout::from_size_align_unchecked(capacity + 8, 8);
+            assert_eq!(
+                block.allocate_first_fit(huge, &mut 0),
+                Err(HoleError::NoFit)
+            );
+
+            // the single hole is large enough, but not past the aligned address
+            let aligned = Layout::from_size_align_unchecked(capacity - 64, 1024);
+            assert_eq!(
+                block.allocate_first_fit(aligned, &mut 0),
+                Err(HoleError::BadAlignment)
+            );
+            assert_eq!(
+                block.allocate_with::<BestFit>(aligned, &mut 0, usize::MAX),
+                Err(HoleError::BadAlignment)
+            );
+
+            // the scan limit is reported as a missing fit
+            let small = Layout::from_size_align_unchecked(64, 8);
+            assert_eq!(
+                block.allocate_with::<FirstFit>(small, &mut 1, 1),
+                Err(HoleError::NoFit)
+            );
+            assert!(block.allocate_first_fit(small, &mut 0).is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dealloc-checks")]
+    #[should_panic(expected = "overlaps the hole")]
+    /// Check freeing memory overlapping a hole is rejected.
+    fn heapblock_overlapping_free() {
+        unsafe {
+            let mut block = [0u64; 512];
+            let addr = NonNull::new_unchecked(block.as_mut_ptr()).cast();
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
+            let layout = Layout::from_size_align_unchecked(64, 8);
+
+            let a = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            let b = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            let _c = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            block.deallocate(b, layout);
+            block.deallocate(a, Layout::from_size_align_unchecked(128, 8));
+        }
+    }
+
+    #[test]
+    /// Check the first violation of the hole list is described.
+    fn heapblock_validate() {
+        unsafe {
+            let mut block = [0u64; 512];
+            let addr = NonNull::new_unchecked(block.as_mut_ptr()).cast();
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
+            let layout = Layout::from_size_align_unchecked(64, 8);
+            assert_eq!(block.validate(), Ok(()));
+
+            let a = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            let _b = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            block.deallocate(a, layout);
+            assert_eq!(block.validate(), Ok(()));
+
+            // grow the first hole over the allocation following it
+            let hole: *mut Hole = &mut **block.first.next.as_mut().expect("no hole");
+            (*hole).size += 128;
+            assert_eq!(
+                block.validate(),
+                Err("hole overlapping the header or the previous hole")
+            );
+            (*hole).size = 4096;
+            assert_eq!(
+                block.validate(),
+                Err("hole extending past the end of the heap block")
+            );
+            (*hole).size = 8;
+            assert_eq!(block.validate(), Err("hole too small to hold its header"));
+        }
+    }
+
+    #[test]
+    /// Check freed neighbours are coalesced into a single hole.
+    fn heapblock_coalesce() {
+        unsafe {
+            let mut block = [0u64; 512];
+            let addr = NonNull::new_unchecked(block.as_mut_ptr()).cast();
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
+            let layout = Layout::from_size_align_unchecked(64, 8);
+
+            let ptrs: Vec<_> = (0..4)
+                .map(|_| block.allocate_first_fit(layout, &mut 0).expect("alloc"))
+                .collect();
+            block.deallocate(ptrs[1], layout);
+            block.deallocate(ptrs[0], layout);
+            block.deallocate(ptrs[2], layout);
+            assert_eq!(block.info().holes, 2);
+
+            // the three slots now form a single hole at the front
+            let large = Layout::from_size_align_unchecked(192, 8);
+            let ptr = block.allocate_first_fit(large, &mut 0).expect("alloc");
+            assert_eq!(ptr, ptrs[0]);
+            assert_eq!(block.info().holes, 1);
+        }
+    }
+
+    #[test]
+    /// Check a tail too small for a hole is reclaimed by the next hole.
+    fn heapblock_shrink_small_tail() {
+        unsafe {
+            let mut block = [0u64; 512];
+            let addr = NonNull::new_unchecked(block.as_mut_ptr()).cast();
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
+            let layout = Layout::from_size_align_unchecked(64, 8);
+            let a = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            let free = block.info().free_bytes;
+
+            assert!(block.resize_in_place(a.as_ptr() as usize, 64, 56));
+            assert_eq!(block.info().free_bytes, free + 8);
+            assert_eq!(block.info().holes, 1);
+            let b = block.allocate_first_fit(layout, &mut 0).expect("alloc");
+            assert_eq!(b.as_ptr() as usize, a.as_ptr() as usize + 56);
+
+            // without a hole right after it, the tail cannot be reclaimed
+            assert!(!block.resize_in_place(a.as_ptr() as usize, 56, 48));
+        }
+    }
+
+    #[test]
+    /// Check the free list is stored within the free memory itself.
+    fn heapblock_hole_overhead() {
+        unsafe {
+            let mut block = [0u64; 512];
+            let addr = NonNull::new_unchecked(block.as_mut_ptr()).cast();
+            let block = HeapBlock::<U4096>::new(addr, 4096, false);
+            let capacity = HeapBlock::<U4096>::capacity();
+            let layout = Layout::from_size_align_unchecked(HeapBlock::<U4096>::min_size(), 8);
+
+            // free every other allocation, leaving many minimal holes
+            let ptrs: Vec<_> = (0..64)
+                .map(|_| block.allocate_first_fit(layout, &mut 0).expect("alloc"))
+                .collect();
+            for ptr in ptrs.iter().step_by(2) {
+                block.deallocate(*ptr, layout);
+            }
+            assert_eq!(block.info().holes, 33);
+            assert_eq!(block.free_bytes(), capacity - 32 * layout.size());
+
+            for ptr in ptrs.iter().skip(1).step_by(2) {
+                block.deallocate(*ptr, layout);
+            }
+            assert_eq!(block.free_bytes(), capacity);
+            assert!(block.is_empty());
+        }
+    }
+
 }
@@ -0,0 +1,132 @@
+//! Introspection of the heap layout.
+
+use core::alloc::AllocError;
+
+/// A description of a single heapblock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// The address of the start of the heapblock.
+    pub base: usize,
+    /// The size of the heapblock, including its header.
+    pub size: usize,
+    /// The number of free bytes in the heapblock.
+    pub free_bytes: usize,
+    /// The number of holes in the free list of the heapblock.
+    pub holes: usize,
+    /// The size of the largest hole of the heapblock.
+    pub largest_hole: usize,
+}
+
+/// A snapshot of the heap usage, as returned by [`Deblockator::stats`].
+///
+/// [`Deblockator::stats`]: crate::Deblockator::stats
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of heapblocks.
+    pub heapblock_count: usize,
+    /// The total size of the heapblocks.
+    pub total_bytes: usize,
+    /// The number of bytes of the heapblocks not in a hole, headers included.
+    pub used_bytes: usize,
+    /// The number of bytes in the holes of the heapblocks.
+    pub free_bytes: usize,
+    /// The number of live allocations made with the underlying allocator.
+    pub large_allocation_count: usize,
+    /// The size of the largest hole across all the heapblocks.
+    pub largest_free_block: usize,
+}
+
+impl Stats {
+    /// Returns the proportion of free bytes outside of the largest hole.
+    ///
+    /// This is `0.0` when all the free memory is contiguous, and approaches
+    /// `1.0` as it is split in many small holes, which no large allocation
+    /// can use. A high value hints at calling [`Deblockator::reclaim`].
+    ///
+    /// [`Deblockator::reclaim`]: crate::Deblockator::reclaim
+    pub fn fragmentation(&self) -> f32 {
+        match self.free_bytes {
+            0 => 0.0,
+            free => 1.0 - self.largest_free_block as f32 / free as f32,
+        }
+    }
+}
+
+/// An opaque identifier of a heapblock, as returned by [`Deblockator::block_id`].
+///
+/// [`Deblockator::block_id`]: crate::Deblockator::block_id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockId(pub(crate) usize);
+
+/// The classification of a pointer, as returned by [`Deblockator::verify_pointer`].
+///
+/// [`Deblockator::verify_pointer`]: crate::Deblockator::verify_pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerKind {
+    /// The pointer lies within an allocation of a heapblock.
+    Small,
+    /// The pointer lies within a large allocation.
+    Large,
+    /// The pointer lies within a free hole of a heapblock.
+    Hole,
+    /// The pointer lies within the header of a heapblock.
+    Header,
+    /// The pointer was not allocated by this allocator.
+    Foreign,
+    /// The pointer is not within a heapblock nor a live large allocation,
+    /// and the underlying allocator cannot tell whether it owns it. Also
+    /// returned if the allocator is poisoned.
+    Unknown,
+}
+
+/// The reason an allocation failed, as returned by [`Deblockator::try_alloc`].
+///
+/// [`Deblockator::try_alloc`]: crate::Deblockator::try_alloc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocFailure {
+    /// A panic occurred while the allocator lock was held.
+    Poisoned,
+    /// No hole could fit the layout, and the heap cannot grow: it is
+    /// frozen, or holds its maximum number of heapblocks.
+    NoFit,
+    /// The layout can never be allocated: it does not fit in a heapblock
+    /// while large allocations are disabled, or its padded size overflows.
+    TooLarge,
+    /// The underlying allocator could not provide the memory.
+    Exhausted,
+}
+
+impl From<AllocFailure> for AllocError {
+    fn from(_: AllocFailure) -> Self {
+        AllocError
+    }
+}
+
+/// The error returned by [`Deblockator::reserve_blocks`] when the underlying
+/// allocator could not provide all the heapblocks.
+///
+/// [`Deblockator::reserve_blocks`]: crate::Deblockator::reserve_blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveError {
+    /// The number of heapblocks created and kept before the failure.
+    pub created: usize,
+}
+
+impl From<ReserveError> for AllocError {
+    fn from(_: ReserveError) -> Self {
+        AllocError
+    }
+}
+
+/// A coarse memory pressure level, as returned by [`Deblockator::pressure`].
+///
+/// [`Deblockator::pressure`]: crate::Deblockator::pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    /// Most of the heap is available.
+    Low,
+    /// The heap utilization reached the medium threshold.
+    Medium,
+    /// The heap utilization reached the high threshold.
+    High,
+}
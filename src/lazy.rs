@@ -0,0 +1,72 @@
+//! A block allocator constructed on first use.
+
+use core::alloc::AllocError;
+use core::alloc::Allocator;
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use spin::Once;
+
+use super::backend::BlockAllocator;
+
+/// A block allocator only constructed when a block is first allocated.
+///
+/// This allows declaring a global [`Deblockator`](crate::Deblockator) with
+/// a backend that cannot be built in a `const` context, or that is
+/// expensive to build, without paying for it until the allocator is used.
+/// See [`Deblockator::new_lazy`](crate::Deblockator::new_lazy).
+pub struct LazyBlocks<A> {
+    init: fn() -> A,
+    allocator: Once<A>,
+}
+
+impl<A> LazyBlocks<A> {
+    /// Create a new block allocator, built by `init` on first use.
+    pub const fn new(init: fn() -> A) -> Self {
+        LazyBlocks {
+            init,
+            allocator: Once::new(),
+        }
+    }
+
+    /// Returns the underlying block allocator, if it was built already.
+    pub fn get(&self) -> Option<&A> {
+        self.allocator.get()
+    }
+
+    /// Returns the underlying block allocator, building it if needed.
+    fn force(&self) -> &A {
+        self.allocator.call_once(self.init)
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for LazyBlocks<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.force().allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.force().deallocate(ptr, layout)
+    }
+}
+
+impl<A: BlockAllocator> BlockAllocator for LazyBlocks<A> {
+    fn remaining(&self) -> Option<usize> {
+        // avoid building the allocator only to answer a prediction
+        self.get().and_then(A::remaining)
+    }
+
+    unsafe fn decommit(&self, ptr: NonNull<u8>, len: usize) {
+        self.force().decommit(ptr, len)
+    }
+
+    fn zeroes_blocks(&self) -> bool {
+        self.force().zeroes_blocks()
+    }
+
+    fn owns(&self, ptr: NonNull<u8>) -> Option<bool> {
+        // nothing was allocated if the allocator was not built yet
+        self.get()
+            .map_or(Some(false), |allocator| allocator.owns(ptr))
+    }
+}
@@ -29,7 +29,9 @@
 //! When a request is made to allocate memory, the allocator will iterate
 //! through all the heapblocks, using a **first-fit** allocation method to try
 //! to find an appropriate free memory location. If no heapblock can fit the
-//! requested layout, then a new heapblock is allocated.
+//! requested layout, then a new heapblock is allocated. The [`BestFit`]
+//! strategy can be selected instead, to use the smallest fitting hole of
+//! each heapblock.
 //!
 //! Allocation of very large layouts (more than `16kB`) are done using the
 //! underlying allocator directly. This avoids the possible case of memory
@@ -38,34 +40,54 @@
 //!
 //! ## Deallocation
 //!
-//! If the allocated layout size is larger than the large layout limit, we
-//! simply transmit the deallocation request to the underlying allocator.
-//! Otherwise, we traverse the heapblocks to find the one the memory block
-//! belongs to. A heapblock is deallocated when it is completely empty.
+//! The base addresses of the large allocations are registered along with
+//! the layout they were allocated with, and their deallocation requests are
+//! transmitted to the underlying allocator with that same layout, whatever
+//! the layout given. Otherwise, we traverse the heapblocks to find
+//! the one the memory block belongs to. A heapblock is deallocated when it is completely empty.
 //!
 //! ## Synchronisation
 //!
 //! The [`Deblockator`] can wraps non-global allocator, and needs a synchronisation
 //! primitive to avoid race conditions. This is done using a *spinning mutex*
-//! from the [`spin`] crate.
+//! by default, but any [`RawMutex`] can be used instead, such as a kernel
+//! mutex putting waiting threads to sleep.
 //!
 //! # Usage
 //!
 //! ## Generic usage
 //!
-//! The provided [`Deblockator`] wraps any object implementing [`Alloc`]. For
-//! instance, to use [`Deblockator`] with `jemalloc` to allocate the
-//! heapblocks:
+//! The provided [`Deblockator`] wraps any object implementing [`Allocator`]
+//! and the [`BlockAllocator`] extension trait. For instance, to use
+//! [`Deblockator`] with `jemalloc` to allocate the heapblocks:
 //! ```rust,no_run
-//! #![feature(global_allocator)]
+//! #![feature(allocator_api)]
 //! extern crate jemallocator;
 //! extern crate deblockator;
 //!
+//! use std::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+//! use std::ptr::NonNull;
+//!
 //! use jemallocator::Jemalloc;
-//! use deblockator::Deblockator;
+//! use deblockator::{BlockAllocator, Deblockator};
+//!
+//! struct JemallocBlocks;
+//!
+//! unsafe impl Allocator for JemallocBlocks {
+//!     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+//!         let ptr = NonNull::new(unsafe { Jemalloc.alloc(layout) }).ok_or(AllocError)?;
+//!         Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+//!     }
+//!
+//!     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+//!         Jemalloc.dealloc(ptr.as_ptr(), layout)
+//!     }
+//! }
+//!
+//! impl BlockAllocator for JemallocBlocks {}
 //!
 //! #[global_allocator]
-//! static GLOBAL: Deblockator<Jemalloc> = Deblockator::new(Jemalloc);
+//! static GLOBAL: Deblockator<JemallocBlocks> = Deblockator::new(JemallocBlocks);
 //! # fn main() {}
 //! ```
 //!
@@ -75,7 +97,6 @@
 //! wraps the `psp2` kernel API using [`psp2-sys`] bindings:
 //!
 //! ```rust,ignore
-//! #![feature(global_allocator)]
 //! extern crate deblockator;
 //! extern crate vitallocator;
 //!
@@ -87,26 +108,88 @@
 //! # fn main() {}
 //! ```
 //!
-//! [`spin`]: https://docs.rs/spin/
+//! A block allocator implementing [`ConstDefault`] does not even need to be
+//! named: `Deblockator::new_default()` can initialize the `static` as well.
+//!
 //! [`typenum`]: https://docs.rs/typenum/
-//! [`Alloc`]: https://doc.rust-lang.org/nightly/std/alloc/trait.Alloc.html
+//! [`Allocator`]: https://doc.rust-lang.org/nightly/std/alloc/trait.Allocator.html
 //! [`Vitallocator`]: https://docs.rs/vitallocator/latest/vitallocator/struct.Vitallocator.html
 //! [`KernelAllocator`]: struct.KernelAllocator.html
 
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![feature(allocator_api)]
-#![feature(alloc_layout_extra)]
-#![feature(const_mut_refs)]
-
-#[cfg(test)]
-use std as core;
 
 extern crate spin;
 extern crate typenum;
 
 mod alloc;
+mod backend;
+#[cfg_attr(not(feature = "event-log"), allow(dead_code))]
+mod event;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fit;
+mod growth;
 mod hole;
+mod info;
+mod lazy;
+mod lock;
+mod pool;
+mod region;
+mod registry;
+mod runtime;
+#[cfg(feature = "counters")]
+mod stats;
+#[cfg(any(test, feature = "std"))]
+mod tuning;
 mod utils;
 
 // Public reexport of the generic allocator.
+pub use alloc::ConstDeblockator;
 pub use alloc::Deblockator;
+// Public reexport of the underlying allocator trait.
+pub use backend::BlockAllocator;
+pub use backend::ConstDefault;
+// Public reexport of the fit strategies.
+pub use fit::BestFit;
+pub use fit::FirstFit;
+pub use fit::FitStrategy;
+// Public reexport of the heapblock growth policies.
+pub use growth::GrowthPolicy;
+// Public reexport of the mutex trait.
+pub use lock::RawMutex;
+pub use lock::SpinRawMutex;
+// Public reexport of the lazily constructed block allocator.
+pub use lazy::LazyBlocks;
+// Public reexport of the fixed pool block allocator.
+pub use pool::StaticPool;
+// Public reexport of the block allocator of single region heaps.
+pub use region::NoBlocks;
+// Public reexport of the runtime configured allocator.
+pub use runtime::RuntimeDeblockator;
+// Public reexport of the heap introspection types.
+pub use info::AllocFailure;
+pub use info::BlockId;
+pub use info::BlockInfo;
+pub use info::PointerKind;
+pub use info::Pressure;
+pub use info::ReserveError;
+pub use info::Stats;
+// Public reexport of the event types.
+pub use event::Event;
+pub use event::EventKind;
+#[cfg(feature = "event-log")]
+pub use event::EVENT_LOG_LEN;
+// Public reexport of the lock-free statistics.
+#[cfg(feature = "counters")]
+pub use stats::ClassStats;
+#[cfg(feature = "counters")]
+pub use stats::QuickStats;
+#[cfg(feature = "counters")]
+pub use stats::SIZE_CLASSES;
+// Public reexport of the address formatting helpers.
+pub use utils::fmt_hex;
+pub use utils::HEX_LEN;
+// Public reexport of the tuning helpers.
+#[cfg(feature = "std")]
+pub use tuning::suggest_block_size;
@@ -0,0 +1,249 @@
+//! A mutex aware of panics occurring while it is held.
+//!
+//! Without `std`, there is no way to know whether a guard is dropped while
+//! unwinding. Instead, guards must be released explicitly with
+//! [`PoisonGuard::unlock`]: a guard dropped any other way (i.e. during a
+//! panic) poisons the mutex, so that the allocator never operates on a heap
+//! left in an inconsistent state.
+//!
+//! The actual locking is delegated to a [`RawMutex`], spinning by default.
+
+use core::hint::spin_loop;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+/// A raw mutual exclusion primitive, used to synchronise the allocator.
+///
+/// Implement this to replace the default [`SpinRawMutex`], for instance
+/// with a mutex provided by the kernel that puts waiting threads to sleep
+/// instead of spinning.
+///
+/// # Safety
+///
+/// Between a call to [`lock`](RawMutex::lock) and the matching call to
+/// [`unlock`](RawMutex::unlock), no other thread sharing the mutex may
+/// return from `lock`. A mutex only providing this guarantee within a
+/// single thread must not be [`Sync`].
+pub unsafe trait RawMutex {
+    /// A mutex in the unlocked state.
+    const INIT: Self;
+
+    /// Acquire the mutex, blocking until it is available.
+    fn lock(&self);
+
+    /// Acquire the mutex if it is available, without blocking.
+    ///
+    /// Returns whether the mutex was acquired. Returns `false` by default,
+    /// as if the mutex was always held: mutexes keeping the default are never
+    /// acquired this way, and the `Debug` output of a
+    /// [`Deblockator`](crate::Deblockator) using them is always `<locked>`.
+    /// Implement this method to get the heap summary instead.
+    fn try_lock(&self) -> bool {
+        false
+    }
+
+    /// Release the mutex.
+    ///
+    /// # Safety
+    ///
+    /// The mutex must be held by the current context.
+    unsafe fn unlock(&self);
+}
+
+/// A [`RawMutex`] spinning until the mutex is available.
+pub struct SpinRawMutex {
+    locked: AtomicBool,
+}
+
+unsafe impl RawMutex for SpinRawMutex {
+    const INIT: Self = SpinRawMutex {
+        locked: AtomicBool::new(false),
+    };
+
+    fn lock(&self) {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// The error returned when locking a poisoned mutex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Poisoned;
+
+/// A mutex that gets poisoned by a panic occurring while it is held.
+pub struct PoisonMutex<M = SpinRawMutex> {
+    raw: M,
+    poisoned: AtomicBool,
+}
+
+impl<M: RawMutex> PoisonMutex<M> {
+    /// Create a new unlocked mutex.
+    pub const fn new() -> Self {
+        PoisonMutex {
+            raw: M::INIT,
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    /// Acquire the mutex, or fail if it was poisoned.
+    pub fn lock(&self) -> Result<PoisonGuard<'_, M>, Poisoned> {
+        self.raw.lock();
+        if self.poisoned.load(Ordering::Acquire) {
+            // SAFETY: the mutex was just acquired
+            unsafe { self.raw.unlock() };
+            return Err(Poisoned);
+        }
+        Ok(PoisonGuard {
+            raw: &self.raw,
+            poisoned: &self.poisoned,
+            armed: true,
+        })
+    }
+
+    /// Acquire the mutex if it is available, or fail if it was poisoned.
+    ///
+    /// Returns `None` if the mutex is held.
+    pub fn try_lock(&self) -> Option<Result<PoisonGuard<'_, M>, Poisoned>> {
+        if !self.raw.try_lock() {
+            return None;
+        }
+        if self.poisoned.load(Ordering::Acquire) {
+            // SAFETY: the mutex was just acquired
+            unsafe { self.raw.unlock() };
+            return Some(Err(Poisoned));
+        }
+        Some(Ok(PoisonGuard {
+            raw: &self.raw,
+            poisoned: &self.poisoned,
+            armed: true,
+        }))
+    }
+
+    /// Acquire the mutex, even if it was poisoned.
+    ///
+    /// Only use this to access data that stays consistent even if a panic
+    /// occurred while the mutex was held.
+    pub fn lock_unchecked(&self) -> PoisonGuard<'_, M> {
+        self.raw.lock();
+        PoisonGuard {
+            raw: &self.raw,
+            poisoned: &self.poisoned,
+            armed: false,
+        }
+    }
+
+    /// Check whether the mutex was poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Acquire)
+    }
+
+    /// Clear the poisoned flag of the mutex.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Release);
+    }
+
+    /// Release the mutex without a guard, poisoning it.
+    ///
+    /// This recovers a mutex whose holder can never release it, e.g. a
+    /// thread killed or aborting while holding it.
+    ///
+    /// # Safety
+    ///
+    /// The mutex must be held, and its holder must not use or release it
+    /// afterwards: this can release a mutex held by another thread.
+    pub unsafe fn force_unlock(&self) {
+        self.poisoned.store(true, Ordering::Release);
+        self.raw.unlock();
+    }
+}
+
+/// A guard poisoning its mutex unless released with [`PoisonGuard::unlock`].
+pub struct PoisonGuard<'a, M: RawMutex = SpinRawMutex> {
+    raw: &'a M,
+    poisoned: &'a AtomicBool,
+    armed: bool,
+}
+
+impl<M: RawMutex> PoisonGuard<'_, M> {
+    /// Release the mutex normally.
+    pub fn unlock(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<M: RawMutex> Drop for PoisonGuard<'_, M> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.poisoned.store(true, Ordering::Release);
+        }
+        // SAFETY: the guard is only built once the mutex is acquired
+        unsafe { self.raw.unlock() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Check the mutex is only poisoned by guards not explicitly released.
+    fn poisonmutex_poison() {
+        let mutex = PoisonMutex::<SpinRawMutex>::new();
+
+        mutex.lock().expect("could not lock").unlock();
+        assert!(!mutex.is_poisoned());
+
+        drop(mutex.lock().expect("could not lock"));
+        assert!(mutex.is_poisoned());
+        assert_eq!(mutex.lock().err(), Some(Poisoned));
+
+        mutex.clear_poison();
+        mutex.lock().expect("could not lock").unlock();
+    }
+
+    #[test]
+    /// Check a mutex whose guard is lost can be released, poisoned.
+    fn poisonmutex_force_unlock() {
+        let mutex = PoisonMutex::<SpinRawMutex>::new();
+
+        // the holder disappears without releasing the mutex
+        core::mem::forget(mutex.lock().expect("could not lock"));
+        unsafe { mutex.force_unlock() };
+        assert!(mutex.is_poisoned());
+        assert_eq!(mutex.lock().err(), Some(Poisoned));
+
+        mutex.clear_poison();
+        mutex.lock().expect("could not lock").unlock();
+        assert!(!mutex.is_poisoned());
+    }
+
+    #[test]
+    /// Check trying to lock fails while the mutex is held, without blocking.
+    fn poisonmutex_try_lock() {
+        let mutex = PoisonMutex::<SpinRawMutex>::new();
+        let guard = mutex.lock().expect("could not lock");
+        assert!(mutex.try_lock().is_none());
+        guard.unlock();
+
+        let guard = mutex.try_lock().expect("held").expect("poisoned");
+        drop(guard);
+        assert_eq!(mutex.try_lock().map(|r| r.err()), Some(Some(Poisoned)));
+    }
+}
@@ -0,0 +1,142 @@
+//! A block allocator over a fixed pool of buffers.
+
+use core::alloc::AllocError;
+use core::alloc::Allocator;
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use super::backend::BlockAllocator;
+
+/// A block allocator handing out buffers from a fixed, caller-supplied pool.
+///
+/// This allows using a [`Deblockator`](crate::Deblockator) without any
+/// dynamic allocation: every heapblock and every large allocation takes one
+/// of the `N` buffers of `BS` bytes, and allocation fails once they are all
+/// in use. Layouts larger than a buffer cannot be allocated.
+///
+/// Buffers are only handed out for layouts they are aligned enough for, so
+/// the pool should be stored with the block alignment of the `Deblockator`:
+/// ```rust
+/// #![feature(allocator_api)]
+/// # use std::alloc::{GlobalAlloc, Layout};
+/// use deblockator::{Deblockator, StaticPool};
+/// use typenum::consts::{U1024, U4096};
+///
+/// #[repr(C, align(4096))]
+/// struct Buffers([[u8; 4096]; 3]);
+///
+/// let mut buffers = Buffers([[0; 4096]; 3]);
+/// let pool = StaticPool::new(&mut buffers.0);
+/// let deblockator: Deblockator<_, U4096, U4096, U1024, U4096> = Deblockator::new(pool);
+/// # unsafe {
+/// #     let layout = Layout::from_size_align(16, 8).unwrap();
+/// #     let ptr = deblockator.alloc(layout);
+/// #     assert!(!ptr.is_null());
+/// #     deblockator.dealloc(ptr, layout);
+/// # }
+/// ```
+pub struct StaticPool<'a, const BS: usize, const N: usize> {
+    buffers: NonNull<[u8; BS]>,
+    used: [Cell<bool>; N],
+    __buffers: PhantomData<&'a mut [[u8; BS]; N]>,
+}
+
+impl<'a, const BS: usize, const N: usize> StaticPool<'a, BS, N> {
+    /// Create a new pool handing out the given buffers.
+    pub const fn new(buffers: &'a mut [[u8; BS]; N]) -> Self {
+        StaticPool {
+            buffers: unsafe { NonNull::new_unchecked(buffers.as_mut_ptr()) },
+            used: [const { Cell::new(false) }; N],
+            __buffers: PhantomData,
+        }
+    }
+
+    /// Returns a pointer to the `i`-th buffer of the pool.
+    fn buffer(&self, i: usize) -> NonNull<u8> {
+        unsafe { self.buffers.add(i).cast() }
+    }
+}
+
+unsafe impl<const BS: usize, const N: usize> Allocator for StaticPool<'_, BS, N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() > BS {
+            return Err(AllocError);
+        }
+        for (i, used) in self.used.iter().enumerate() {
+            let ptr = self.buffer(i);
+            if !used.get() && (ptr.as_ptr() as usize).is_multiple_of(layout.align()) {
+                used.set(true);
+                return Ok(NonNull::slice_from_raw_parts(ptr, BS));
+            }
+        }
+        Err(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        let offset = ptr.as_ptr() as usize - self.buffer(0).as_ptr() as usize;
+        match self.used.get(offset / BS) {
+            Some(used) if offset.is_multiple_of(BS) => used.set(false),
+            _ => panic!("no such block !"),
+        }
+    }
+}
+
+impl<const BS: usize, const N: usize> BlockAllocator for StaticPool<'_, BS, N> {
+    fn remaining(&self) -> Option<usize> {
+        Some(self.used.iter().filter(|used| !used.get()).count() * BS)
+    }
+
+    fn owns(&self, ptr: NonNull<u8>) -> Option<bool> {
+        let offset = (ptr.as_ptr() as usize).wrapping_sub(self.buffer(0).as_ptr() as usize);
+        Some(self.used.get(offset / BS).is_some_and(Cell::get))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::alloc::GlobalAlloc;
+
+    use typenum::consts::U2048;
+    use typenum::consts::U4096;
+
+    use super::super::Deblockator;
+
+    #[repr(C, align(4096))]
+    struct Buffers([[u8; 4096]; 3]);
+
+    #[test]
+    /// Check the allocator runs over a pool of 3 buffers.
+    fn staticpool_deblockator() {
+        let mut buffers = Buffers([[0; 4096]; 3]);
+        let base = buffers.0.as_ptr() as usize;
+        let pool = StaticPool::new(&mut buffers.0);
+        let va: Deblockator<_, U4096, U4096, U2048, U4096> = Deblockator::new(pool);
+
+        unsafe {
+            let small = Layout::from_size_align(1024, 8).expect("bad layout");
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let too_large = Layout::from_size_align(5000, 8).expect("bad layout");
+
+            let a = va.alloc(small);
+            assert!((base..base + 4096).contains(&(a as usize)));
+            let b = va.alloc(large);
+            assert_eq!(b as usize, base + 4096);
+            let c = va.alloc(large);
+            assert_eq!(c as usize, base + 8192);
+            assert!(va.alloc(too_large).is_null());
+
+            // the pool is exhausted
+            assert!(va.alloc(large).is_null());
+            va.dealloc(b, large);
+            assert_eq!(va.alloc(large), b);
+
+            va.dealloc(a, small);
+            va.dealloc(b, large);
+            va.dealloc(c, large);
+        }
+    }
+}
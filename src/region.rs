@@ -0,0 +1,41 @@
+//! A block allocator for heaps confined to a single region.
+
+use core::alloc::AllocError;
+use core::alloc::Allocator;
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use super::backend::BlockAllocator;
+use super::backend::ConstDefault;
+
+/// A block allocator that never provides any block.
+///
+/// Used by [`Deblockator::new_in_region`](crate::Deblockator::new_in_region)
+/// to confine the heap to a single static region: allocations fail once the
+/// region is full, instead of growing the heap.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoBlocks;
+
+unsafe impl Allocator for NoBlocks {
+    fn allocate(&self, _layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Err(AllocError)
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        unreachable!("no block was ever allocated")
+    }
+}
+
+impl ConstDefault for NoBlocks {
+    const DEFAULT: Self = NoBlocks;
+}
+
+impl BlockAllocator for NoBlocks {
+    fn remaining(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn owns(&self, _ptr: NonNull<u8>) -> Option<bool> {
+        Some(false)
+    }
+}
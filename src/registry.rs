@@ -0,0 +1,168 @@
+//! Registry of the live large allocations.
+//!
+//! Large allocations are made with the underlying allocator directly, and do
+//! not belong to any heapblock. The registry remembers their base address and
+//! the layout they were allocated with, so that their deallocation does not
+//! depend on the layout given by the caller.
+//!
+//! The registry is intrusive: each large allocation is padded to hold its own
+//! entry at its end, and the entries are chained together. There is thus no
+//! limit on the number of large allocations tracked.
+
+use core::alloc::Layout;
+use core::cmp::max;
+use core::mem::align_of;
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use super::utils::checked_align_up;
+
+/// The entry of a large allocation, stored in its last bytes.
+#[repr(C)]
+struct LargeEntry {
+    next: Option<NonNull<LargeEntry>>,
+    base: usize,
+    layout: Layout,
+}
+
+/// A linked list of the live large allocations, with their layouts.
+pub struct LargeRegistry {
+    head: Option<NonNull<LargeEntry>>,
+}
+
+impl LargeRegistry {
+    /// Create a new empty registry.
+    pub const fn new() -> Self {
+        LargeRegistry { head: None }
+    }
+
+    /// Create a layout fitting `layout` followed by its entry, padded to `align`.
+    ///
+    /// Returns `None` if the padded size overflows.
+    pub fn padded(layout: Layout, align: usize) -> Option<Layout> {
+        let size = checked_align_up(layout.size(), align_of::<LargeEntry>())?;
+        let size = checked_align_up(size.checked_add(size_of::<LargeEntry>())?, align)?;
+        let align = max(max(layout.align(), align), align_of::<LargeEntry>());
+        Layout::from_size_align(size, align).ok()
+    }
+
+    /// Register the large allocation at `base`, made with `layout`.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have been returned by [`padded`](Self::padded), and
+    /// `base` must point to a live allocation of `layout`.
+    pub unsafe fn insert(&mut self, base: NonNull<u8>, layout: Layout) {
+        let entry = base.add(layout.size() - size_of::<LargeEntry>()).cast();
+        entry.write(LargeEntry {
+            next: self.head,
+            base: base.as_ptr() as usize,
+            layout,
+        });
+        self.head = Some(entry);
+    }
+
+    /// Check whether a large allocation at `addr` is registered.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.entries().any(|(base, _)| base == addr)
+    }
+
+    /// Unregister the most recent large allocation, returning its base address and layout.
+    pub fn pop(&mut self) -> Option<(usize, Layout)> {
+        let entry = unsafe { self.head?.read() };
+        self.head = entry.next;
+        Some((entry.base, entry.layout))
+    }
+
+    /// Unregister the large allocation at `addr`, returning the layout it was made with.
+    ///
+    /// Returns `None` if it was not registered.
+    pub fn remove(&mut self, addr: usize) -> Option<Layout> {
+        let mut link: *mut Option<NonNull<LargeEntry>> = &mut self.head;
+        unsafe {
+            while let Some(entry) = *link {
+                let entry = entry.as_ptr();
+                if (*entry).base == addr {
+                    *link = (*entry).next;
+                    return Some((*entry).layout);
+                }
+                link = &mut (*entry).next;
+            }
+        }
+        None
+    }
+
+    /// Iterate over the base addresses and layouts of the registered allocations.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, Layout)> + '_ {
+        let mut next = self.head;
+        core::iter::from_fn(move || {
+            let entry = unsafe { next?.as_ref() };
+            next = entry.next;
+            Some((entry.base, entry.layout))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(64))]
+    struct Buffer([u8; 256]);
+
+    #[test]
+    /// Check allocations are padded to hold their entry.
+    fn largeregistry_padded() {
+        let entry = size_of::<LargeEntry>();
+        let layout = |size, align| Layout::from_size_align(size, align).expect("bad layout");
+        assert_eq!(
+            LargeRegistry::padded(layout(3000, 8), 4096),
+            Some(layout(4096, 4096))
+        );
+        assert_eq!(
+            LargeRegistry::padded(layout(4096 - entry, 8), 4096),
+            Some(layout(4096, 4096))
+        );
+        assert_eq!(
+            LargeRegistry::padded(layout(4097 - entry, 8), 4096),
+            Some(layout(8192, 4096))
+        );
+        assert_eq!(
+            LargeRegistry::padded(layout(1, 1), 1),
+            Some(layout(8 + entry, align_of::<LargeEntry>()))
+        );
+        assert_eq!(
+            LargeRegistry::padded(layout(isize::MAX as usize - 4000, 8), 4096),
+            None
+        );
+    }
+
+    #[test]
+    /// Check any number of allocations can be registered and removed.
+    fn largeregistry_insert_remove() {
+        let mut buffers: Vec<Buffer> = (0..40).map(|_| Buffer([0; 256])).collect();
+        let layout = LargeRegistry::padded(Layout::from_size_align(100, 8).unwrap(), 64).unwrap();
+        let bases: Vec<usize> = buffers
+            .iter_mut()
+            .map(|b| b.0.as_mut_ptr() as usize)
+            .collect();
+
+        let mut registry = LargeRegistry::new();
+        for &base in &bases {
+            unsafe { registry.insert(NonNull::new(base as *mut u8).unwrap(), layout) };
+        }
+        assert!(bases.iter().all(|&base| registry.contains(base)));
+        assert_eq!(registry.entries().count(), bases.len());
+        assert!(!registry.contains(bases[0] + 64));
+
+        assert_eq!(registry.remove(bases[0]), Some(layout));
+        assert!(!registry.contains(bases[0]));
+        assert_eq!(registry.remove(bases[0]), None);
+        assert_eq!(registry.remove(bases[20]), Some(layout));
+
+        // the most recent allocation is popped first
+        assert_eq!(registry.pop(), Some((bases[39], layout)));
+        while registry.pop().is_some() {}
+        assert_eq!(registry.entries().count(), 0);
+    }
+}
@@ -0,0 +1,522 @@
+//! A deblockator whose parameters are only known at runtime.
+
+use core::alloc::AllocError;
+use core::alloc::Allocator;
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::cmp::max;
+use core::mem::align_of;
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use super::backend::BlockAllocator;
+use super::hole::HeapBlock;
+use super::hole::Hole;
+use super::lock::PoisonMutex;
+use super::registry::LargeRegistry;
+use super::utils::checked_align_up;
+
+/// A [`Deblockator`](crate::Deblockator) with parameters set at construction.
+///
+/// The block size, block alignment, large size and large alignment have the
+/// same meaning as the compile-time parameters of the generic `Deblockator`,
+/// but are stored as fields. This allows choosing them at runtime, at the
+/// cost of a few more computations on every call. A large size of zero
+/// disables individual allocations, as with `U0`. Large allocations are
+/// registered as with the generic type: they are freed by address whatever
+/// the layout given, and given back when the allocator is dropped.
+///
+/// This is a separate, minimal implementation rather than a wrapper around
+/// the generic type: it always uses first-fit, retains the first heapblock
+/// when empty, and has none of the configuration, introspection or
+/// statistics of the generic `Deblockator`.
+///
+/// ```rust
+/// #![feature(allocator_api)]
+/// # use std::alloc::{GlobalAlloc, Layout};
+/// use deblockator::{RuntimeDeblockator, StaticPool};
+///
+/// #[repr(C, align(4096))]
+/// struct Buffers([[u8; 4096]; 2]);
+///
+/// let mut buffers = Buffers([[0; 4096]; 2]);
+/// let pool = StaticPool::new(&mut buffers.0);
+/// let deblockator = RuntimeDeblockator::new(pool, 4096, 4096, 1024, 4096);
+/// # unsafe {
+/// #     let layout = Layout::from_size_align(16, 8).unwrap();
+/// #     let ptr = deblockator.alloc(layout);
+/// #     assert!(!ptr.is_null());
+/// #     deblockator.dealloc(ptr, layout);
+/// # }
+/// ```
+pub struct RuntimeDeblockator<A>
+where
+    A: BlockAllocator,
+{
+    mutex: PoisonMutex,
+    block_allocator: A,
+    first_block: UnsafeCell<Option<&'static mut HeapBlock>>,
+    large_registry: UnsafeCell<LargeRegistry>,
+    block_size: usize,
+    block_align: usize,
+    large_size: usize,
+    large_align: usize,
+}
+
+unsafe impl<A> Sync for RuntimeDeblockator<A> where A: BlockAllocator {}
+
+unsafe impl<A> Send for RuntimeDeblockator<A> where A: BlockAllocator {}
+
+impl<A> RuntimeDeblockator<A>
+where
+    A: BlockAllocator,
+{
+    /// Create a new allocator with the given parameters.
+    ///
+    /// # Panics
+    ///
+    /// If either alignment is not a power of two, if `block_align` is not
+    /// enough to store a heapblock header, or if `block_size` is too small
+    /// to fit a heapblock header and a minimal allocation.
+    pub const fn new(
+        alloc: A,
+        block_size: usize,
+        block_align: usize,
+        large_size: usize,
+        large_align: usize,
+    ) -> Self {
+        assert!(
+            block_align.is_power_of_two(),
+            "block alignment is not a power of two"
+        );
+        assert!(
+            large_align.is_power_of_two(),
+            "large alignment is not a power of two"
+        );
+        assert!(
+            block_align >= align_of::<HeapBlock>(),
+            "block alignment is too small"
+        );
+        assert!(
+            block_size >= size_of::<HeapBlock>() + 2 * size_of::<usize>()
+                && block_size.is_multiple_of(align_of::<Hole>()),
+            "block size is too small or misaligned"
+        );
+        RuntimeDeblockator {
+            mutex: PoisonMutex::new(),
+            block_allocator: alloc,
+            first_block: UnsafeCell::new(None),
+            large_registry: UnsafeCell::new(LargeRegistry::new()),
+            block_size,
+            block_align,
+            large_size,
+            large_align,
+        }
+    }
+
+    /// Check whether a panic occurred while the allocator lock was held.
+    pub fn is_poisoned(&self) -> bool {
+        self.mutex.is_poisoned()
+    }
+
+    /// Pad the layout to the minimum legal size of a heapblock allocation.
+    ///
+    /// Returns `None` if the padded size overflows.
+    fn block_layout(&self, layout: Layout) -> Option<Layout> {
+        let size = max(<HeapBlock>::min_size(), layout.size());
+        let size = checked_align_up(size, align_of::<Hole>())?;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
+
+    /// Check whether a layout must be allocated with the underlying allocator.
+    fn is_large(&self, layout: Layout) -> bool {
+        (self.large_size != 0 && layout.size() >= self.large_size)
+            || self.block_layout(layout).is_none_or(|block_layout| {
+                !<HeapBlock>::fits_fresh(block_layout, self.block_size, self.block_align)
+            })
+    }
+
+    /// Create a layout for the underlying allocator fitting a large layout
+    /// and its registry entry.
+    ///
+    /// Returns `None` if the padded size overflows.
+    fn padded(&self, layout: Layout) -> Option<Layout> {
+        LargeRegistry::padded(layout, self.large_align)
+    }
+
+    /// Allocate a new heapblock.
+    unsafe fn new_block(&self) -> Result<&'static mut HeapBlock, AllocError> {
+        let layout = Layout::from_size_align_unchecked(self.block_size, self.block_align);
+        let ptr = self.block_allocator.allocate(layout)?.cast::<HeapBlock>();
+        // a faulty allocator could return a block wrapping around the address space
+        if (ptr.as_ptr() as usize)
+            .checked_add(self.block_size)
+            .is_none()
+        {
+            self.block_allocator.deallocate(ptr.cast(), layout);
+            return Err(AllocError);
+        }
+        Ok(<HeapBlock>::new(
+            ptr,
+            self.block_size,
+            self.block_allocator.zeroes_blocks(),
+        ))
+    }
+
+    /// Allocate memory as described by `layout`.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn alloc_locked(&self, layout: Layout) -> *mut u8 {
+        let block_layout = match self.block_layout(layout) {
+            Some(block_layout) if !self.is_large(layout) => block_layout,
+            // layouts not fitting a heapblock cannot be allocated without large allocations
+            _ if self.large_size == 0 => return ::core::ptr::null_mut::<u8>(),
+            _ => {
+                let padded = match self.padded(layout) {
+                    Some(padded) => padded,
+                    None => return ::core::ptr::null_mut::<u8>(),
+                };
+                return match self.block_allocator.allocate(padded) {
+                    Ok(ptr) => {
+                        (*self.large_registry.get()).insert(ptr.cast(), padded);
+                        ptr.as_ptr() as *mut u8
+                    }
+                    Err(_) => ::core::ptr::null_mut::<u8>(),
+                };
+            }
+        };
+
+        // traverse the heap blocks to find an allocatable block
+        let mut owner: *mut HeapBlock = ::core::ptr::null_mut();
+        let mut next_block: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut block) = *next_block {
+            block.verify();
+            if let Ok(ptr) = block.allocate_first_fit(block_layout, &mut 0) {
+                return ptr.as_ptr();
+            }
+            owner = &mut **block;
+            next_block = &mut block.next;
+        }
+
+        // no block can contain the requested layout: allocate a new one
+        let new_block = match self.new_block() {
+            Ok(block) => block,
+            Err(_) => return ::core::ptr::null_mut::<u8>(),
+        };
+        let ptr = match new_block.allocate_first_fit(block_layout, &mut 0) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => return ::core::ptr::null_mut::<u8>(),
+        };
+        *next_block = Some(new_block);
+        if let Some(owner) = owner.as_mut() {
+            owner.seal();
+        }
+        ptr
+    }
+
+    /// Deallocate the memory at `ptr` with the given `layout`.
+    ///
+    /// Must be called with the lock held.
+    unsafe fn dealloc_locked(&self, ptr: *mut u8, layout: Layout) {
+        // replay the layout of the allocation rather than padding the given one
+        if let Some(padded) = (*self.large_registry.get()).remove(ptr as usize) {
+            return self.free_large(ptr, padded);
+        }
+
+        let mut owner: *mut HeapBlock = ::core::ptr::null_mut();
+        let mut block: *mut Option<&'static mut HeapBlock> = self.first_block.get();
+        while let Some(ref mut b) = *block {
+            b.verify();
+            if b.contains(ptr) {
+                let block_layout = self.block_layout(layout).expect("invalid layout");
+                b.deallocate(NonNull::new_unchecked(ptr), block_layout);
+                // release empty heapblocks, except for the first one
+                if b.is_empty() && !owner.is_null() {
+                    let freed = (*block).take().unwrap();
+                    *block = freed.next.take();
+                    (*owner).seal();
+                    let layout =
+                        Layout::from_size_align_unchecked(self.block_size, self.block_align);
+                    self.block_allocator
+                        .deallocate(NonNull::from(freed).cast(), layout);
+                }
+                return;
+            }
+            owner = &mut **b;
+            block = &mut b.next;
+        }
+        panic!("double free !")
+    }
+
+    /// Give the large allocation at `ptr`, made with `padded`, back to the underlying allocator.
+    unsafe fn free_large(&self, ptr: *mut u8, padded: Layout) {
+        #[cfg(feature = "zero-on-free")]
+        ptr.write_bytes(0, padded.size());
+        self.block_allocator
+            .decommit(NonNull::new_unchecked(ptr), padded.size());
+        self.block_allocator
+            .deallocate(NonNull::new_unchecked(ptr), padded);
+    }
+}
+
+unsafe impl<A> GlobalAlloc for RuntimeDeblockator<A>
+where
+    A: BlockAllocator,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.mutex.lock() {
+            Ok(lock) => {
+                let ptr = self.alloc_locked(layout);
+                lock.unlock();
+                ptr
+            }
+            Err(_) => ::core::ptr::null_mut::<u8>(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // the memory is leaked if the heap may be inconsistent
+        if let Ok(lock) = self.mutex.lock() {
+            self.dealloc_locked(ptr, layout);
+            lock.unlock();
+        }
+    }
+}
+
+unsafe impl<A> Allocator for RuntimeDeblockator<A>
+where
+    A: BlockAllocator,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc(ptr.as_ptr(), layout)
+    }
+}
+
+/// Give all heapblocks and large allocations back to the underlying allocator, before it is dropped.
+impl<A> Drop for RuntimeDeblockator<A>
+where
+    A: BlockAllocator,
+{
+    fn drop(&mut self) {
+        // the heap may be inconsistent after a panic: leak it instead
+        if self.mutex.is_poisoned() {
+            return;
+        }
+        while let Some((addr, padded)) = self.large_registry.get_mut().pop() {
+            unsafe { self.free_large(addr as *mut u8, padded) };
+        }
+        let layout =
+            unsafe { Layout::from_size_align_unchecked(self.block_size, self.block_align) };
+        let mut next = self.first_block.get_mut().take();
+        while let Some(block) = next {
+            next = block.next.take();
+            unsafe {
+                self.block_allocator
+                    .deallocate(NonNull::from(block).cast(), layout)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::StaticPool;
+
+    #[repr(C, align(4096))]
+    struct Buffers([[u8; 4096]; 3]);
+
+    #[test]
+    /// Check the allocator uses the block size given at construction.
+    fn runtimedeblockator_blocks() {
+        let mut buffers = Buffers([[0; 4096]; 3]);
+        let base = buffers.0.as_ptr() as usize;
+        let pool = StaticPool::new(&mut buffers.0);
+        let va = RuntimeDeblockator::new(pool, 4096, 4096, 2048, 4096);
+
+        unsafe {
+            let layout = Layout::from_size_align(1500, 8).expect("bad layout");
+            let a = va.alloc(layout);
+            let b = va.alloc(layout);
+            assert!((base..base + 4096).contains(&(a as usize)));
+            assert!((base..base + 4096).contains(&(b as usize)));
+
+            // the third allocation does not fit in the first heapblock
+            let c = va.alloc(layout);
+            assert!((base + 4096..base + 8192).contains(&(c as usize)));
+
+            // large allocations take a block of their own
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let d = va.alloc(large);
+            assert_eq!(d as usize, base + 8192);
+            assert!(va.alloc(large).is_null());
+
+            // the second heapblock is released once empty
+            va.dealloc(c, layout);
+            assert_eq!(va.alloc(large) as usize, base + 4096);
+            va.dealloc((base + 4096) as *mut u8, large);
+
+            va.dealloc(a, layout);
+            va.dealloc(b, layout);
+            va.dealloc(d, large);
+        }
+    }
+
+    #[test]
+    /// Check the allocator behaves as the generic one with the same parameters.
+    fn runtimedeblockator_matches_deblockator() {
+        use typenum::consts::U2048;
+        use typenum::consts::U4096;
+
+        use super::super::Deblockator;
+
+        let mut runtime_buffers = Buffers([[0; 4096]; 3]);
+        let mut generic_buffers = Buffers([[0; 4096]; 3]);
+        let runtime_base = runtime_buffers.0.as_ptr() as usize;
+        let generic_base = generic_buffers.0.as_ptr() as usize;
+        let runtime = RuntimeDeblockator::new(
+            StaticPool::new(&mut runtime_buffers.0),
+            4096,
+            4096,
+            2048,
+            4096,
+        );
+        let generic: Deblockator<StaticPool<4096, 3>, U4096, U4096, U2048, U4096> =
+            Deblockator::new(StaticPool::new(&mut generic_buffers.0));
+
+        // sizes one byte short of the hole alignment, so that tags do not change the layout
+        let layouts: Vec<_> = [23, 95, 1495, 2999, 63, 799]
+            .iter()
+            .map(|&size| Layout::from_size_align(size, 8).expect("bad layout"))
+            .collect();
+        unsafe {
+            let mut ptrs = Vec::new();
+            for &layout in &layouts {
+                let (r, g) = (runtime.alloc(layout), generic.alloc(layout));
+                assert_eq!(r as usize - runtime_base, g as usize - generic_base);
+                ptrs.push((r, g));
+            }
+            for (&(r, g), &layout) in ptrs.iter().zip(&layouts).step_by(2) {
+                runtime.dealloc(r, layout);
+                generic.dealloc(g, layout);
+            }
+            for &layout in &layouts[..3] {
+                let (r, g) = (runtime.alloc(layout), generic.alloc(layout));
+                assert_eq!(r as usize - runtime_base, g as usize - generic_base);
+            }
+        }
+    }
+
+    #[test]
+    /// Check a large size of zero disables large allocations, as with the generic allocator.
+    fn runtimedeblockator_matches_deblockator_no_large() {
+        use typenum::consts::U0;
+        use typenum::consts::U4096;
+
+        use super::super::Deblockator;
+
+        let mut runtime_buffers = Buffers([[0; 4096]; 3]);
+        let mut generic_buffers = Buffers([[0; 4096]; 3]);
+        let runtime_base = runtime_buffers.0.as_ptr() as usize;
+        let generic_base = generic_buffers.0.as_ptr() as usize;
+        let runtime =
+            RuntimeDeblockator::new(StaticPool::new(&mut runtime_buffers.0), 4096, 4096, 0, 4096);
+        let generic: Deblockator<StaticPool<4096, 3>, U4096, U4096, U0, U4096> =
+            Deblockator::new(StaticPool::new(&mut generic_buffers.0));
+
+        // sizes one byte short of the hole alignment, so that tags do not change the layout
+        let layouts: Vec<_> = [23, 2999, 5000, 1495, 3999]
+            .iter()
+            .map(|&size| Layout::from_size_align(size, 8).expect("bad layout"))
+            .collect();
+        unsafe {
+            let mut ptrs = Vec::new();
+            for &layout in &layouts {
+                let (r, g) = (runtime.alloc(layout), generic.alloc(layout));
+                assert_eq!(r.is_null(), g.is_null(), "{:?}", layout);
+                if !r.is_null() {
+                    assert_eq!(r as usize - runtime_base, g as usize - generic_base);
+                    ptrs.push((r, g, layout));
+                }
+            }
+            // only the layout not fitting a heapblock failed
+            assert_eq!(ptrs.len(), layouts.len() - 1);
+            for (r, g, layout) in ptrs {
+                runtime.dealloc(r, layout);
+                generic.dealloc(g, layout);
+            }
+        }
+    }
+
+    #[test]
+    /// Check large allocations are freed by address, and given back on drop.
+    fn runtimedeblockator_large_registry() {
+        use core::cell::Cell;
+
+        /// A pool counting the live blocks.
+        struct CountingPool<'a>(StaticPool<'a, 4096, 3>, &'a Cell<usize>);
+
+        unsafe impl Allocator for CountingPool<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                let ptr = self.0.allocate(layout)?;
+                self.1.set(self.1.get() + 1);
+                Ok(ptr)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.1.set(self.1.get() - 1);
+                self.0.deallocate(ptr, layout)
+            }
+        }
+
+        impl BlockAllocator for CountingPool<'_> {}
+
+        let mut buffers = Buffers([[0; 4096]; 3]);
+        let live = Cell::new(0);
+        {
+            let pool = CountingPool(StaticPool::new(&mut buffers.0), &live);
+            let va = RuntimeDeblockator::new(pool, 4096, 4096, 2048, 4096);
+            let large = Layout::from_size_align(3000, 8).expect("bad layout");
+            let small = Layout::from_size_align(100, 8).expect("bad layout");
+            unsafe {
+                let a = va.alloc(large);
+                let b = va.alloc(large);
+                assert!(!a.is_null() && !b.is_null());
+                assert_eq!(live.get(), 2);
+
+                // a layout that would not be large does not misroute the deallocation
+                va.dealloc(a, small);
+                assert_eq!(live.get(), 1);
+            }
+        }
+        // the remaining large allocation is given back on drop
+        assert_eq!(live.get(), 0);
+    }
+
+    #[test]
+    /// Check layouts whose padded size overflows are not allocated.
+    fn runtimedeblockator_padded_overflow() {
+        let mut buffers = Buffers([[0; 4096]; 3]);
+        let pool = StaticPool::new(&mut buffers.0);
+        let va = RuntimeDeblockator::new(pool, 4096, 4096, 2048, 4096);
+        let huge = Layout::from_size_align(isize::MAX as usize - 4000, 8).expect("bad layout");
+        assert_eq!(va.padded(huge), None);
+        assert!(unsafe { va.alloc(huge) }.is_null());
+    }
+
+    #[test]
+    #[should_panic]
+    /// Check invalid parameters are rejected.
+    fn runtimedeblockator_invalid() {
+        let mut buffers = Buffers([[0; 4096]; 3]);
+        let pool = StaticPool::new(&mut buffers.0);
+        RuntimeDeblockator::new(pool, 4096, 3000, 2048, 4096);
+    }
+}
@@ -0,0 +1,140 @@
+//! Allocation counters and statistics.
+
+use core::cmp::min;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+/// The number of size classes tracked by [`ClassStats`].
+///
+/// Class `i` contains the heapblock allocations of `2^(i+3)` exclusive
+/// to `2^(i+4)` inclusive bytes, and the last class all larger ones.
+pub const SIZE_CLASSES: usize = 16;
+
+/// Returns the size class of a heapblock allocation of `size` bytes.
+pub fn size_class(size: usize) -> usize {
+    let log2 = size.next_power_of_two().trailing_zeros() as usize;
+    min(log2.saturating_sub(4), SIZE_CLASSES - 1)
+}
+
+/// Counters updated on every allocation, when the `counters` feature is enabled.
+#[derive(Debug, Clone, Copy)]
+pub struct Counters {
+    /// The number of allocations served from heapblocks.
+    pub allocations: usize,
+    /// The total number of holes examined by those allocations.
+    pub scanned: usize,
+    /// The reuse statistics of each size class.
+    pub classes: [ClassStats; SIZE_CLASSES],
+    /// The number of allocations made with the underlying allocator.
+    pub large_allocations: usize,
+    /// The number of deallocations into heapblocks.
+    pub small_deallocations: usize,
+    /// The number of deallocations made with the underlying allocator.
+    pub large_deallocations: usize,
+    /// The number of bytes currently allocated by the user.
+    pub used_bytes: usize,
+    /// The highest number of bytes allocated by the user at once.
+    pub peak_used_bytes: usize,
+}
+
+impl Counters {
+    /// Create a new set of counters, all set to zero.
+    pub const fn new() -> Self {
+        Counters {
+            allocations: 0,
+            scanned: 0,
+            classes: [ClassStats::new(); SIZE_CLASSES],
+            large_allocations: 0,
+            small_deallocations: 0,
+            large_deallocations: 0,
+            used_bytes: 0,
+            peak_used_bytes: 0,
+        }
+    }
+}
+
+/// How the heapblock allocations of a size class were served.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClassStats {
+    /// The number of allocations served from an existing heapblock.
+    pub reused: usize,
+    /// The number of allocations that required a new heapblock.
+    pub grown: usize,
+}
+
+impl ClassStats {
+    /// Create new statistics, all set to zero.
+    pub const fn new() -> Self {
+        ClassStats {
+            reused: 0,
+            grown: 0,
+        }
+    }
+
+    /// Returns the proportion of allocations served from an existing heapblock.
+    ///
+    /// A low reuse rate for a busy class means its allocations keep growing
+    /// the heap, which hints at a block size too small for the workload.
+    pub fn reuse_rate(&self) -> f32 {
+        match self.reused + self.grown {
+            0 => 0.0,
+            n => self.reused as f32 / n as f32,
+        }
+    }
+}
+
+/// A best-effort snapshot of the allocator usage, read without locking.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuickStats {
+    /// The number of bytes currently allocated by the user.
+    pub used_bytes: usize,
+    /// The number of heapblocks currently allocated.
+    pub block_count: usize,
+}
+
+/// Statistics updated atomically, so that they can be read without locking.
+pub struct AtomicStats {
+    used_bytes: AtomicUsize,
+    block_count: AtomicUsize,
+}
+
+impl AtomicStats {
+    /// Create a new set of statistics, all set to zero.
+    pub const fn new() -> Self {
+        AtomicStats {
+            used_bytes: AtomicUsize::new(0),
+            block_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Account for `size` bytes being allocated.
+    pub fn alloc(&self, size: usize) {
+        self.used_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Account for `size` bytes being deallocated.
+    pub fn dealloc(&self, size: usize) {
+        self.used_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Account for a heapblock being allocated.
+    pub fn block_create(&self) {
+        self.block_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account for a heapblock being deallocated.
+    pub fn block_free(&self) {
+        self.block_count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Read the current statistics.
+    ///
+    /// The values are read independently, and may not be consistent with
+    /// each other if the allocator is being used concurrently.
+    pub fn snapshot(&self) -> QuickStats {
+        QuickStats {
+            used_bytes: self.used_bytes.load(Ordering::Relaxed),
+            block_count: self.block_count.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Helpers to choose the compile-time parameters of a `Deblockator`.
+//!
+//! These functions only analyse workloads recorded beforehand, and are meant
+//! to be run on a host rather than on the target.
+
+use core::mem::size_of;
+
+use super::hole::HeapBlock;
+use super::utils::align_up;
+
+/// The smallest block size that can be suggested.
+const MIN_BLOCK_SIZE: usize = 1 << 12;
+/// The largest block size that can be suggested.
+const MAX_BLOCK_SIZE: usize = 1 << 20;
+/// The alignment large allocations are padded to, as with the default `LA`.
+const LARGE_ALIGN: usize = 1 << 12;
+
+/// Suggest a block size for the given histogram of allocation sizes.
+///
+/// The histogram lists allocation sizes along with how many times they
+/// occur. Each power of two between `4kB` and `1MB` is evaluated as a block
+/// size, with the large size limit set to 1/4th of the block size, and the
+/// one wasting the fewest bytes is returned. Wasted bytes are estimated as:
+///
+/// * the heapblock headers,
+/// * half a heapblock, for the free space of the last heapblock,
+/// * the padding of large allocations to the default large alignment.
+///
+/// ```rust
+/// # use deblockator::suggest_block_size;
+/// let histogram = [(32, 5000), (256, 800), (10000, 100)];
+/// assert_eq!(suggest_block_size(&histogram), 65536);
+/// ```
+pub fn suggest_block_size(histogram: &[(usize, u64)]) -> usize {
+    let mut best = (u128::MAX, MIN_BLOCK_SIZE);
+    let mut block_size = MIN_BLOCK_SIZE;
+    while block_size <= MAX_BLOCK_SIZE {
+        let cost = block_size_cost(histogram, block_size);
+        if cost < best.0 {
+            best = (cost, block_size);
+        }
+        block_size <<= 1;
+    }
+    best.1
+}
+
+/// Estimate the number of bytes wasted by a workload with the given block size.
+fn block_size_cost(histogram: &[(usize, u64)], block_size: usize) -> u128 {
+    let header = size_of::<HeapBlock>() as u128;
+    let capacity = (block_size as u128).saturating_sub(header);
+    let large_size = block_size / 4;
+
+    let mut small_bytes = 0u128;
+    let mut large_waste = 0u128;
+    for &(size, count) in histogram {
+        if size >= large_size {
+            let padding = align_up(size, LARGE_ALIGN) - size;
+            large_waste += padding as u128 * count as u128;
+        } else {
+            small_bytes += size as u128 * count as u128;
+        }
+    }
+
+    let blocks = small_bytes.div_ceil(capacity);
+    blocks * header + block_size as u128 / 2 + large_waste
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Check small workloads get small blocks, and larger ones larger blocks.
+    fn suggest_block_size_histogram() {
+        let small = [(16, 1000), (32, 4000), (128, 500)];
+        assert_eq!(suggest_block_size(&small), MIN_BLOCK_SIZE);
+
+        // 10kB allocations only fit in heapblocks of at least 64kB
+        let mixed = [(32, 5000), (256, 800), (10000, 1000)];
+        let suggested = suggest_block_size(&mixed);
+        assert!(suggested.is_power_of_two());
+        assert!(suggested >= 65536);
+        assert!(suggested <= MAX_BLOCK_SIZE);
+
+        assert_eq!(suggest_block_size(&[]), MIN_BLOCK_SIZE);
+    }
+}
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use core::cmp::max;
+
 /// Align downwards.
 ///
 /// Returns the greatest x with alignment `align` so that x <= addr.
@@ -14,10 +16,119 @@ pub fn align_down(addr: usize, align: usize) -> usize {
     }
 }
 
+/// Align downwards, or return `None` if `align` is not a power of 2.
+pub fn checked_align_down(addr: usize, align: usize) -> Option<usize> {
+    if align.is_power_of_two() {
+        Some(addr & !(align - 1))
+    } else {
+        None
+    }
+}
+
 /// Align upwards.
 ///
 /// Returns the smallest x with alignment `align` so that x >= addr.
 /// The alignment must be a power of 2.
+///
+/// # Panics
+///
+/// If `align` is not a power of 2, or if the aligned address overflows.
 pub fn align_up(addr: usize, align: usize) -> usize {
-    align_down(addr + align - 1, align)
+    match checked_align_up(addr, align) {
+        Some(addr) => addr,
+        None if align.is_power_of_two() => panic!("aligned address overflows"),
+        None => panic!("`align` must be a power of 2"),
+    }
+}
+
+/// Align upwards, or return `None` if the aligned address overflows.
+///
+/// Also returns `None` if `align` is not a power of 2, so that callers
+/// inside the allocator can report a failure instead of panicking.
+pub fn checked_align_up(addr: usize, align: usize) -> Option<usize> {
+    let mask = align.checked_sub(1)?;
+    checked_align_down(addr.checked_add(mask)?, align)
+}
+
+/// The maximum length of an address formatted with [`fmt_hex`].
+pub const HEX_LEN: usize = 2 + 2 * ::core::mem::size_of::<usize>();
+
+/// Format `value` as a lowercase hexadecimal number prefixed with `0x`.
+///
+/// The number is written at the start of `buf`, and the written part is
+/// returned. An empty string is returned if `buf` is too small: a buffer of
+/// [`HEX_LEN`] bytes can hold any value.
+pub fn fmt_hex(value: usize, buf: &mut [u8]) -> &str {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let digits = max(
+        1,
+        (usize::BITS - value.leading_zeros()).div_ceil(4) as usize,
+    );
+    let len = digits + 2;
+    if buf.len() < len {
+        return "";
+    }
+
+    buf[0] = b'0';
+    buf[1] = b'x';
+    for i in 0..digits {
+        buf[len - 1 - i] = DIGITS[(value >> (4 * i)) & 0xF];
+    }
+    ::core::str::from_utf8(&buf[..len]).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Check values are formatted with the right number of digits.
+    fn fmt_hex_values() {
+        let mut buf = [0; HEX_LEN];
+        assert_eq!(fmt_hex(0, &mut buf), "0x0");
+        assert_eq!(fmt_hex(0xA, &mut buf), "0xa");
+        assert_eq!(fmt_hex(0xBEEF, &mut buf), "0xbeef");
+        assert_eq!(fmt_hex(0x1000, &mut buf), "0x1000");
+        assert_eq!(fmt_hex(usize::MAX, &mut buf).len(), HEX_LEN);
+        assert!(fmt_hex(usize::MAX, &mut buf)[2..]
+            .bytes()
+            .all(|digit| digit == b'f'));
+    }
+
+    #[test]
+    /// Check nothing is written to a buffer too small for the value.
+    fn fmt_hex_short_buffer() {
+        let mut buf = [0; 5];
+        assert_eq!(fmt_hex(0xFFF, &mut buf), "0xfff");
+        assert_eq!(fmt_hex(0x1000, &mut buf), "");
+        assert_eq!(fmt_hex(0, &mut []), "");
+    }
+
+    #[test]
+    /// Check aligning an address past the end of the address space fails.
+    fn checked_align_up_overflow() {
+        assert_eq!(checked_align_up(4097, 4096), Some(8192));
+        assert_eq!(checked_align_up(usize::MAX - 4094, 4096), None);
+        assert_eq!(checked_align_up(usize::MAX, 1), Some(usize::MAX));
+        assert_eq!(checked_align_up(usize::MAX, 2), None);
+    }
+
+    #[test]
+    /// Check invalid alignments are rejected instead of panicking.
+    fn checked_align_invalid() {
+        assert_eq!(checked_align_up(4097, 0), None);
+        assert_eq!(checked_align_down(4097, 0), None);
+        assert_eq!(checked_align_up(4097, 3), None);
+        assert_eq!(checked_align_down(4097, 3), None);
+        assert_eq!(checked_align_down(4097, 4096), Some(4096));
+        assert_eq!(checked_align_down(usize::MAX, 2), Some(usize::MAX - 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "`align` must be a power of 2")]
+    /// Check the panicking version still rejects invalid alignments.
+    fn align_up_invalid() {
+        align_up(4097, 3);
+    }
 }
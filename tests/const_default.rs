@@ -0,0 +1,42 @@
+#![feature(allocator_api)]
+
+extern crate deblockator;
+
+use std::alloc::AllocError;
+use std::alloc::Allocator;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::ptr::NonNull;
+
+use deblockator::BlockAllocator;
+use deblockator::ConstDefault;
+use deblockator::Deblockator;
+
+mod cases;
+
+/// Expose `System` to allocate heapblocks, with a constant default value.
+struct SystemBlocks;
+
+unsafe impl Allocator for SystemBlocks {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        System.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        System.deallocate(ptr, layout)
+    }
+}
+
+impl BlockAllocator for SystemBlocks {}
+
+impl ConstDefault for SystemBlocks {
+    const DEFAULT: Self = SystemBlocks;
+}
+
+#[global_allocator]
+static GLOBAL: Deblockator<SystemBlocks> = Deblockator::new_default();
+
+#[test]
+fn test_small_alloc() {
+    cases::small_alloc();
+}
@@ -1,13 +1,38 @@
+#![feature(allocator_api)]
+
 extern crate jemallocator;
 extern crate deblockator;
 
+use std::alloc::AllocError;
+use std::alloc::Allocator;
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::ptr::NonNull;
+
+use deblockator::BlockAllocator;
 use jemallocator::Jemalloc;
 use deblockator::Deblockator;
 
 mod cases;
 
+/// Expose `Jemalloc` through the `Allocator` trait to allocate heapblocks.
+struct JemallocBlocks;
+
+unsafe impl Allocator for JemallocBlocks {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { Jemalloc.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        Jemalloc.dealloc(ptr.as_ptr(), layout)
+    }
+}
+
+impl BlockAllocator for JemallocBlocks {}
+
 #[global_allocator]
-static GLOBAL: Deblockator<Jemalloc> = Deblockator::new(Jemalloc);
+static GLOBAL: Deblockator<JemallocBlocks> = Deblockator::new(JemallocBlocks);
 
 #[test]
 fn test_small_alloc() {